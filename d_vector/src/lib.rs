@@ -10,7 +10,11 @@ use std::{
     ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign},
 };
 
+/// Floating-point type of every component; double precision with the `f64` feature.
+#[cfg(not(feature = "f64"))]
 pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
 
 pub fn reset_array<const D: usize>(vectors: &mut [DVector<D>]) {
     for vector in vectors.iter_mut() {
@@ -0,0 +1,287 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    potential::PotentialEnergy,
+    prop::{degrees_of_freedom, mvv_sum},
+    state::MolecularState,
+};
+use d_vector::{DVector, Real};
+use std::{cell::Cell, fmt::Debug};
+
+/// Pressure coupling for NPT runs. `Job::run` calls `couple` for half a step
+/// before and after every integration step.
+pub trait Barostat<const D: usize>: Debug {
+    fn couple(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &mut dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    );
+    /// Instantaneous pressure seen at the last coupling.
+    fn pressure(&self) -> Real;
+}
+
+/// Kinetic plus virial pressure of atoms of the given `masses`, the Boltzmann
+/// constant being 1.
+pub fn instant_pressure<const D: usize>(
+    vel: &[DVector<D>],
+    masses: &[Real],
+    potential_energy: &dyn PotentialEnergy<D>,
+    volume: Real,
+) -> Real {
+    (mvv_sum(vel, masses) + potential_energy.virial_sum()) / (D as Real * volume)
+}
+
+/// Kinetic plus virial pressure tensor; `vel` must not include any streaming velocity.
+pub fn pressure_tensor<const D: usize>(
+    vel: &[DVector<D>],
+    masses: &[Real],
+    potential_energy: &dyn PotentialEnergy<D>,
+    volume: Real,
+) -> [[Real; D]; D] {
+    let mut tensor = potential_energy.virial_tensor();
+    for (v, m) in vel.iter().zip(masses) {
+        for (row, a) in tensor.iter_mut().zip(v.components()) {
+            for (p, b) in row.iter_mut().zip(v.components()) {
+                *p += m * a * b;
+            }
+        }
+    }
+    tensor.map(|row| row.map(|p| p / volume))
+}
+
+/// Scales the box and atom positions by the same factor.
+fn scale_system<const D: usize>(
+    factor: Real,
+    pos: &mut [DVector<D>],
+    boundaries: &mut dyn BoundaryConditions<D>,
+) {
+    boundaries.scale(factor);
+    for position in pos.iter_mut() {
+        *position = factor * &*position;
+    }
+}
+
+/// Berendsen weak coupling: relaxes the pressure to `target` with time
+/// constant `tau`. Good for equilibration, does not sample the NPT ensemble.
+#[derive(Debug)]
+pub struct Berendsen {
+    target: Real,
+    tau: Real,
+    compressibility: Real,
+    pressure: Cell<Real>,
+}
+
+impl Berendsen {
+    pub fn new(target: Real, tau: Real) -> Self {
+        Self {
+            target,
+            tau,
+            compressibility: 1.,
+            pressure: Cell::new(0.),
+        }
+    }
+
+    /// Isothermal compressibility used to convert pressure mismatch into volume change.
+    pub fn compressibility(mut self, compressibility: Real) -> Self {
+        self.compressibility = compressibility;
+        self
+    }
+}
+
+/// Largest relative change of the box length in one coupling, which keeps
+/// a pressure far from the target from turning the box inside out.
+const BERENDSEN_MAX_SCALING: Real = 0.01;
+
+impl<const D: usize> Barostat<D> for Berendsen {
+    fn couple(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &mut dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) {
+        let masses = state.atom_masses();
+        let pressure = instant_pressure(
+            &state.get_vel(),
+            &masses,
+            potential_energy,
+            boundaries.volume(),
+        );
+        self.pressure.set(pressure);
+        let mu = (1. - self.compressibility * delta_t / self.tau * (self.target - pressure))
+            .max(0.)
+            .powf(1. / D as Real)
+            .clamp(1. - BERENDSEN_MAX_SCALING, 1. + BERENDSEN_MAX_SCALING);
+        scale_system(mu, &mut state.get_pos(), boundaries);
+    }
+
+    fn pressure(&self) -> Real {
+        self.pressure.get()
+    }
+}
+
+/// Isotropic Martyna–Tobias–Klein barostat, the isotropic limit of
+/// Parrinello–Rahman: the logarithm of the volume gets its own momentum.
+/// Alone it samples NPH; combined with a thermostat, NPT.
+#[derive(Debug)]
+pub struct Mtk {
+    target: Real,
+    temperature: Real,
+    tau: Real,
+    v_eps: Cell<Real>,
+    pressure: Cell<Real>,
+}
+
+impl Mtk {
+    /// `temperature` only sets the barostat mass together with `tau`.
+    pub fn new(target: Real, temperature: Real, tau: Real) -> Self {
+        Self {
+            target,
+            temperature,
+            tau,
+            v_eps: Cell::new(0.),
+            pressure: Cell::new(0.),
+        }
+    }
+
+    /// Rate of change of the logarithm of the volume over `D`.
+    pub fn strain_rate(&self) -> Real {
+        self.v_eps.get()
+    }
+
+    /// Enthalpy plus the barostat kinetic energy, which NPH dynamics conserve.
+    /// `potential_energy` must hold the forces of the current positions.
+    pub fn conserved_energy<const D: usize>(
+        &self,
+        state: &dyn MolecularState<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+        volume: Real,
+    ) -> Real {
+        let masses = state.atom_masses();
+        let mass = self.mass::<D>(degrees_of_freedom(state));
+        let vel = state.get_vel();
+        let v_eps = self.v_eps.get();
+        0.5 * mvv_sum(&vel, &masses)
+            + potential_energy.u_sum()
+            + self.target * volume
+            + 0.5 * mass * v_eps * v_eps
+    }
+
+    fn mass<const D: usize>(&self, dof: Real) -> Real {
+        (dof + D as Real) * self.temperature * self.tau * self.tau
+    }
+}
+
+impl<const D: usize> Barostat<D> for Mtk {
+    fn couple(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &mut dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) {
+        let masses = state.atom_masses();
+        let dof = degrees_of_freedom(state);
+        let mut vel = state.get_vel();
+        let alpha = 1. + D as Real / dof;
+        let volume = boundaries.volume();
+        let pressure = instant_pressure(&vel, &masses, potential_energy, volume);
+        self.pressure.set(pressure);
+
+        let mvv_sum = mvv_sum(&vel, &masses);
+        let force = D as Real * volume * (pressure - self.target) + (alpha - 1.) * mvv_sum;
+        let v_eps = self.v_eps.get() + delta_t * force / self.mass::<D>(dof);
+        self.v_eps.set(v_eps);
+
+        let vel_scale = (-alpha * v_eps * delta_t).exp();
+        for velocity in vel.iter_mut() {
+            *velocity = vel_scale * &*velocity;
+        }
+        scale_system((v_eps * delta_t).exp(), &mut state.get_pos(), boundaries);
+    }
+
+    fn pressure(&self) -> Real {
+        self.pressure.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boundaries::Region,
+        initial_state::{cubic_lattice, randomize_vectors},
+        integrator::{Integrator, Leapfrog},
+        lennard_jones::LennardJones,
+        potential::NoInteraction,
+        state::State,
+    };
+
+    fn lattice_state(n_mol: usize, density: Real) -> (Region<3>, State<3>) {
+        let (region, pos) = cubic_lattice::<3>(n_mol, density);
+        let state = State::default();
+        let n_mol = pos.len();
+        *state.get_pos() = pos;
+        *state.get_vel() = vec![DVector::default(); n_mol];
+        *state.get_acc() = vec![DVector::default(); n_mol];
+        *state.get_images() = vec![[0; 3]; n_mol];
+        randomize_vectors(&mut state.get_vel(), 1.);
+        (region, state)
+    }
+
+    #[test]
+    fn berendsen_relaxes_ideal_gas() {
+        let (mut region, state) = lattice_state(64, 0.5);
+        let barostat = Berendsen::new(0.8, 0.5);
+        let integrator = Leapfrog;
+        integrator.prime(&state, &region, &NoInteraction);
+        for _ in 0..2000 {
+            barostat.couple(0.005, &state, &mut region, &NoInteraction);
+            integrator
+                .single_step(0.01, &state, &region, &NoInteraction)
+                .unwrap();
+            barostat.couple(0.005, &state, &mut region, &NoInteraction);
+        }
+        let n_mol = state.get_pos().len() as Real;
+        let expected_volume = n_mol * 1. / 3. / 0.8;
+        assert!((region.volume() / expected_volume - 1.).abs() < 1e-3);
+        assert!((Barostat::<3>::pressure(&barostat) - 0.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn berendsen_far_from_target_scales_by_at_most_a_percent() {
+        let (mut region, state) = lattice_state(64, 0.5);
+        let barostat = Berendsen::new(1000., 0.5);
+        let length = region.dimensions()[0];
+        Barostat::<3>::couple(&barostat, 0.005, &state, &mut region, &NoInteraction);
+        assert!((region.dimensions()[0] / length - 0.99).abs() < 1e-5);
+        assert!(state.get_pos().iter().all(|r| r.length().is_finite()));
+    }
+
+    #[test]
+    fn mtk_conserves_enthalpy() {
+        let (mut region, state) = lattice_state(64, 0.7);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let barostat = Mtk::new(2., 1., 0.5);
+        let integrator = Leapfrog;
+        integrator.prime(&state, &region, &wca);
+        let initial_volume = region.volume();
+        let initial = barostat.conserved_energy(&state, &wca, region.volume());
+        // The volume oscillates, so look at its largest change over the run.
+        let mut largest_change = 0 as Real;
+        for _ in 0..2000 {
+            barostat.couple(0.001, &state, &mut region, &wca);
+            integrator
+                .single_step(0.002, &state, &region, &wca)
+                .unwrap();
+            barostat.couple(0.001, &state, &mut region, &wca);
+            largest_change = largest_change.max((region.volume() - initial_volume).abs());
+        }
+        let conserved = barostat.conserved_energy(&state, &wca, region.volume());
+        assert!((conserved - initial).abs() < 1e-2 * initial.abs());
+        assert!(largest_change > 1e-2 * initial_volume);
+    }
+}
@@ -0,0 +1,637 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    potential::{AtomicReal, PotentialEnergy},
+    state::MolecularState,
+};
+use d_vector::{reset_array, DVector, Real};
+use std::sync::{atomic::Ordering, Mutex, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BondStyle {
+    /// `k (r - r0)² / 2`.
+    Harmonic { k: Real, r0: Real },
+    /// `-k r_max² ln(1 - r² / r_max²) / 2`, infinite from `r_max` on.
+    Fene { k: Real, r_max: Real },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AngleStyle {
+    /// `k (θ - θ0)² / 2`.
+    Harmonic { k: Real, theta0: Real },
+    /// `k (1 + cos θ)`, lowest for a straight angle.
+    Cosine { k: Real },
+}
+
+/// Bonds of the topology, all of the same style.
+#[derive(Debug)]
+pub struct Bonds {
+    style: BondStyle,
+    terms: RwLock<Vec<[usize; 2]>>,
+    sums: Sums,
+}
+
+/// Angles of the topology, all of the same style.
+#[derive(Debug)]
+pub struct Angles {
+    style: AngleStyle,
+    terms: RwLock<Vec<[usize; 3]>>,
+    sums: Sums,
+}
+
+/// Periodic torsion `k (1 + cos(n φ - δ))` of the proper dihedrals of the
+/// topology, or of its impropers. φ is zero for the cis conformation.
+#[derive(Debug)]
+pub struct Dihedrals {
+    k: Real,
+    multiplicity: usize,
+    phase: Real,
+    improper: bool,
+    terms: RwLock<Vec<[usize; 4]>>,
+    sums: Sums,
+}
+
+impl Bonds {
+    pub fn harmonic(k: Real, r0: Real) -> Self {
+        Self::new(BondStyle::Harmonic { k, r0 })
+    }
+
+    /// Finitely extensible nonlinear elastic bonds; Kremer–Grest chains use
+    /// `k` = 30 and `r_max` = 1.5 together with WCA between all atoms.
+    pub fn fene(k: Real, r_max: Real) -> Self {
+        Self::new(BondStyle::Fene { k, r_max })
+    }
+
+    pub fn new(style: BondStyle) -> Self {
+        Self {
+            style,
+            terms: RwLock::new(vec![]),
+            sums: Sums::default(),
+        }
+    }
+
+    /// Force value (to be multiplied by the bond vector) and energy.
+    /// An over-stretched FENE bond has infinite energy and no force.
+    fn interaction(&self, rr: Real) -> (Real, Real) {
+        match self.style {
+            BondStyle::Harmonic { k, r0 } => {
+                let r = rr.sqrt();
+                (-k * (r - r0) / r, 0.5 * k * (r - r0) * (r - r0))
+            }
+            BondStyle::Fene { k, r_max } => {
+                let stretch = 1. - rr / (r_max * r_max);
+                if stretch <= 0. {
+                    return (0., Real::INFINITY);
+                }
+                (-k / stretch, -0.5 * k * r_max * r_max * stretch.ln())
+            }
+        }
+    }
+
+    fn term<const D: usize>(&self, [a, b]: &[DVector<D>; 2]) -> (Real, [DVector<D>; 2]) {
+        let dr = b - a;
+        let (force_value, u) = self.interaction(dr.square_length());
+        let force = force_value * &dr;
+        (u, [-1. * &force, force])
+    }
+}
+
+impl Angles {
+    pub fn harmonic(k: Real, theta0: Real) -> Self {
+        Self::new(AngleStyle::Harmonic { k, theta0 })
+    }
+
+    pub fn cosine(k: Real) -> Self {
+        Self::new(AngleStyle::Cosine { k })
+    }
+
+    pub fn new(style: AngleStyle) -> Self {
+        Self {
+            style,
+            terms: RwLock::new(vec![]),
+            sums: Sums::default(),
+        }
+    }
+
+    /// Energy and its derivative with respect to the cosine of the angle.
+    fn interaction(&self, cos: Real) -> (Real, Real) {
+        match self.style {
+            AngleStyle::Harmonic { k, theta0 } => {
+                let theta = cos.acos();
+                let sin = theta.sin().max(1e-6);
+                let d_theta = theta - theta0;
+                (0.5 * k * d_theta * d_theta, -k * d_theta / sin)
+            }
+            AngleStyle::Cosine { k } => (k * (1. + cos), k),
+        }
+    }
+
+    fn term<const D: usize>(&self, [a, vertex, b]: &[DVector<D>; 3]) -> (Real, [DVector<D>; 3]) {
+        let (a, b) = (a - vertex, b - vertex);
+        let (aa, bb, ab) = (a.square_length(), b.square_length(), &a * &b);
+        let norm = 1. / (aa * bb).sqrt();
+        let cos = (ab * norm).clamp(-1., 1.);
+        let (u, du_dcos) = self.interaction(cos);
+        let force_a = -du_dcos * &(&(norm * &b) - &(cos / aa * &a));
+        let force_b = -du_dcos * &(&(norm * &a) - &(cos / bb * &b));
+        let force_vertex = -1. * &(&force_a + &force_b);
+        (u, [force_a, force_vertex, force_b])
+    }
+}
+
+impl Dihedrals {
+    /// Acts on the proper dihedrals of the topology.
+    pub fn proper(k: Real, multiplicity: usize, phase: Real) -> Self {
+        Self {
+            k,
+            multiplicity,
+            phase,
+            improper: false,
+            terms: RwLock::new(vec![]),
+            sums: Sums::default(),
+        }
+    }
+
+    /// Acts on the impropers of the topology instead.
+    pub fn improper(k: Real, multiplicity: usize, phase: Real) -> Self {
+        Self {
+            improper: true,
+            ..Self::proper(k, multiplicity, phase)
+        }
+    }
+
+    fn term(&self, [i, j, k, l]: &[DVector<3>; 4]) -> (Real, [DVector<3>; 4]) {
+        let (r_ij, r_kj, r_kl) = (i - j, k - j, k - l);
+        let (m, n) = (cross(&r_ij, &r_kj), cross(&r_kj, &r_kl));
+        let (mm, nn, kj) = (m.square_length(), n.square_length(), r_kj.length());
+        if mm < 1e-12 || nn < 1e-12 {
+            return (0., std::array::from_fn(|_| DVector::default()));
+        }
+        let phi = (kj * (&r_ij * &n)).atan2(&m * &n);
+        let angle = self.multiplicity as Real * phi - self.phase;
+        let u = self.k * (1. + angle.cos());
+        let du_dphi = -self.k * self.multiplicity as Real * angle.sin();
+
+        let force_i = (-du_dphi * kj / mm) * &m;
+        let force_l = (du_dphi * kj / nn) * &n;
+        let p = (&r_ij * &r_kj) / (kj * kj);
+        let q = (&r_kl * &r_kj) / (kj * kj);
+        let s = &(p * &force_i) - &(q * &force_l);
+        let force_j = &s - &force_i;
+        let force_k = -1. * &(&force_l + &s);
+        (u, [force_i, force_j, force_k, force_l])
+    }
+}
+
+impl<const D: usize> PotentialEnergy<D> for Bonds {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        let terms = self.terms.read().unwrap();
+        let (u_sum, w_sum) = add_terms(&terms, pos, acc, boundaries, |atoms| self.term(atoms));
+        self.sums.store(u_sum, w_sum);
+    }
+
+    fn attach(&self, state: &dyn MolecularState<D>) {
+        self.terms
+            .write()
+            .unwrap()
+            .clone_from(&state.get_topology().bonds);
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        let terms = self.terms.read().unwrap();
+        atom_terms(&terms, atom, position, pos, boundaries, |atoms| {
+            self.term(atoms).0
+        })
+    }
+
+    fn u_sum(&self) -> Real {
+        self.sums.u_sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.sums.virial_sum()
+    }
+
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        self.sums.virial_tensor()
+    }
+}
+
+impl<const D: usize> PotentialEnergy<D> for Angles {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        let terms = self.terms.read().unwrap();
+        let (u_sum, w_sum) = add_terms(&terms, pos, acc, boundaries, |atoms| self.term(atoms));
+        self.sums.store(u_sum, w_sum);
+    }
+
+    fn attach(&self, state: &dyn MolecularState<D>) {
+        self.terms
+            .write()
+            .unwrap()
+            .clone_from(&state.get_topology().angles);
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        let terms = self.terms.read().unwrap();
+        atom_terms(&terms, atom, position, pos, boundaries, |atoms| {
+            self.term(atoms).0
+        })
+    }
+
+    fn u_sum(&self) -> Real {
+        self.sums.u_sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.sums.virial_sum()
+    }
+
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        self.sums.virial_tensor()
+    }
+}
+
+impl PotentialEnergy<3> for Dihedrals {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        let terms = self.terms.read().unwrap();
+        let (u_sum, w_sum) = add_terms(&terms, pos, acc, boundaries, |atoms| self.term(atoms));
+        self.sums.store(u_sum, w_sum);
+    }
+
+    fn attach(&self, state: &dyn MolecularState<3>) {
+        let topology = state.get_topology();
+        let terms = if self.improper {
+            &topology.impropers
+        } else {
+            &topology.dihedrals
+        };
+        self.terms.write().unwrap().clone_from(terms);
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<3>,
+        pos: &[DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) -> Real {
+        let terms = self.terms.read().unwrap();
+        atom_terms(&terms, atom, position, pos, boundaries, |atoms| {
+            self.term(atoms).0
+        })
+    }
+
+    fn u_sum(&self) -> Real {
+        self.sums.u_sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.sums.virial_sum()
+    }
+
+    fn virial_tensor(&self) -> [[Real; 3]; 3] {
+        self.sums.virial_tensor()
+    }
+}
+
+/// Energy and virial of the last forces computed.
+#[derive(Debug, Default)]
+struct Sums {
+    u_sum: AtomicReal,
+    v_sum: AtomicReal,
+    w_sum: Mutex<Vec<Real>>,
+}
+
+impl Sums {
+    fn store<const D: usize>(&self, u_sum: Real, w_sum: [[Real; D]; D]) {
+        let trace = (0..D).map(|a| w_sum[a][a]).sum();
+        self.u_sum.store(u_sum, Ordering::SeqCst);
+        self.v_sum.store(trace, Ordering::SeqCst);
+        *self.w_sum.lock().unwrap() = w_sum.into_iter().flatten().collect();
+    }
+
+    fn u_sum(&self) -> Real {
+        self.u_sum.load(Ordering::SeqCst)
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.v_sum.load(Ordering::SeqCst)
+    }
+
+    fn virial_tensor<const D: usize>(&self) -> [[Real; D]; D] {
+        let mut tensor = [[0.; D]; D];
+        for (row, w) in tensor
+            .iter_mut()
+            .zip(self.w_sum.lock().unwrap().chunks_exact(D))
+        {
+            row.copy_from_slice(w);
+        }
+        tensor
+    }
+}
+
+/// Adds the forces of every term to `acc` and returns the energy and virial
+/// tensor. `interaction` gets the atoms of a term unwrapped into one image,
+/// each next to the previous one, so that the virial is `Σ r ⊗ f` over them.
+fn add_terms<const D: usize, const N: usize>(
+    terms: &[[usize; N]],
+    pos: &[DVector<D>],
+    acc: &mut [DVector<D>],
+    boundaries: &dyn BoundaryConditions<D>,
+    interaction: impl Fn(&[DVector<D>; N]) -> (Real, [DVector<D>; N]),
+) -> (Real, [[Real; D]; D]) {
+    let mut u_sum = 0 as Real;
+    let mut w_sum = [[0 as Real; D]; D];
+    for atoms in terms {
+        let unwrapped = unwrap(atoms, |n| &pos[n], boundaries);
+        let (u, forces) = interaction(&unwrapped);
+        u_sum += u;
+        for ((atom, r), force) in atoms.iter().zip(unwrapped.iter()).zip(forces.iter()) {
+            acc[*atom] += force;
+            for (row, x) in w_sum.iter_mut().zip(r.components()) {
+                for (w, f) in row.iter_mut().zip(force.components()) {
+                    *w += x * f;
+                }
+            }
+        }
+    }
+    (u_sum, w_sum)
+}
+
+/// Energy of the terms holding `atom`, with it moved to `position`.
+/// A new atom, `atom` equal to `pos.len()`, is in no term yet.
+fn atom_terms<const D: usize, const N: usize>(
+    terms: &[[usize; N]],
+    atom: usize,
+    position: &DVector<D>,
+    pos: &[DVector<D>],
+    boundaries: &dyn BoundaryConditions<D>,
+    energy: impl Fn(&[DVector<D>; N]) -> Real,
+) -> Real {
+    let at = |n: usize| if n == atom { position } else { &pos[n] };
+    terms
+        .iter()
+        .filter(|atoms| atoms.contains(&atom))
+        .map(|atoms| energy(&unwrap(atoms, at, boundaries)))
+        .sum()
+}
+
+/// Positions of the atoms of a term in one image, the first at the origin.
+fn unwrap<'a, const D: usize, const N: usize>(
+    atoms: &[usize; N],
+    at: impl Fn(usize) -> &'a DVector<D>,
+    boundaries: &dyn BoundaryConditions<D>,
+) -> [DVector<D>; N] {
+    let mut unwrapped: [DVector<D>; N] = std::array::from_fn(|_| DVector::default());
+    for n in 1..N {
+        let mut dr = at(atoms[n]) - at(atoms[n - 1]);
+        boundaries.minimum_image(&mut dr);
+        unwrapped[n] = &unwrapped[n - 1] + &dr;
+    }
+    unwrapped
+}
+
+fn cross(a: &DVector<3>, b: &DVector<3>) -> DVector<3> {
+    let ([a0, a1, a2], [b0, b1, b2]) = (a.components(), b.components());
+    DVector::from([a1 * b2 - a2 * b1, a2 * b0 - a0 * b2, a0 * b1 - a1 * b0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, state::State, topology::Topology};
+
+    /// A branched molecule straddling the corner of the box.
+    fn molecule() -> (Region<3>, State<3>) {
+        let state = State::default();
+        for x in [
+            [2.3, 2.4, 2.2],
+            [-2.4, 2.2, -2.3],
+            [-2.1, -2.4, -2.2],
+            [-1.3, -2.0, -2.4],
+            [-1.0, -1.1, 2.4],
+            [-2.0, -2.2, -1.2],
+        ] {
+            state.insert_atom(DVector::from(x));
+        }
+        *state.get_topology() = Topology::default()
+            .chain(0..5)
+            .improper([2, 1, 3, 5])
+            .bond(2, 5)
+            .angle(1, 2, 5);
+        (Region::new([5., 5., 5.]), state)
+    }
+
+    /// Forces against the finite differences of the energy, the virial
+    /// against its definition with the molecule unwrapped, and the energy
+    /// of each atom against the change of the total when it moves.
+    fn check(potential: &dyn PotentialEnergy<3>) {
+        let (region, state) = molecule();
+        potential.attach(&state);
+        let mut pos = state.get_pos().clone();
+        let mut acc = vec![DVector::default(); pos.len()];
+        potential.compute_forces(&pos, &mut acc, &region);
+        assert!(potential.u_sum() > 0.);
+
+        let unwrapped = [
+            [2.3, 2.4, 2.2],
+            [2.6, 2.2, 2.7],
+            [2.9, 2.6, 2.8],
+            [3.7, 3.0, 2.6],
+            [4.0, 3.9, 2.4],
+            [3.0, 2.8, 3.8],
+        ];
+        let mut virial = 0.;
+        for (x, a) in unwrapped.iter().zip(acc.iter()) {
+            virial += &DVector::from(*x) * a;
+        }
+        assert!((virial - potential.virial_sum()).abs() < 1e-3);
+
+        let h = 1e-3;
+        for atom in 0..pos.len() {
+            for axis in 0..3 {
+                let mut shift = [0.; 3];
+                shift[axis] = h;
+                let mut scratch = acc.clone();
+                pos[atom] += &DVector::from(shift);
+                potential.compute_forces(&pos, &mut scratch, &region);
+                let u_plus = potential.u_sum();
+                pos[atom] -= &(2. * &DVector::from(shift));
+                potential.compute_forces(&pos, &mut scratch, &region);
+                let u_minus = potential.u_sum();
+                pos[atom] += &DVector::from(shift);
+                let force = -(u_plus - u_minus) / (2. * h);
+                let expected = acc[atom].components()[axis];
+                assert!(
+                    (force - expected).abs() < 2e-2 * (1. + expected.abs()),
+                    "atom {} axis {}: {} vs {}",
+                    atom,
+                    axis,
+                    force,
+                    expected
+                );
+            }
+        }
+
+        potential.compute_forces(&pos, &mut acc, &region);
+        let u_sum = potential.u_sum();
+        let shift = DVector::from([0.05, -0.1, 0.08]);
+        for atom in 0..pos.len() {
+            let position = &pos[atom] + &shift;
+            let du = potential.atom_energy(atom, &position, &pos, &region)
+                - potential.atom_energy(atom, &pos[atom], &pos, &region);
+            let mut moved = pos.clone();
+            moved[atom] = position;
+            potential.compute_forces(&moved, &mut acc, &region);
+            assert!(
+                (du - (potential.u_sum() - u_sum)).abs() < 1e-3 * (1. + du.abs()),
+                "atom {}",
+                atom
+            );
+        }
+        let new_atom = DVector::from([0.1, 0.2, 0.3]);
+        assert_eq!(
+            0.,
+            potential.atom_energy(pos.len(), &new_atom, &pos, &region)
+        );
+    }
+
+    #[test]
+    fn bonds() {
+        check(&Bonds::harmonic(100., 0.5));
+        check(&Bonds::fene(30., 1.5));
+    }
+
+    #[test]
+    fn fene_energy_is_infinite_beyond_r_max() {
+        let (region, state) = molecule();
+        let bonds = Bonds::fene(30., 1.5);
+        bonds.attach(&state);
+        let pos = state.get_pos().clone();
+        let mut acc = vec![DVector::default(); pos.len()];
+        let far = &pos[1] + &DVector::from([1.6, 0., 0.]);
+        assert_eq!(Real::INFINITY, bonds.atom_energy(0, &far, &pos, &region));
+
+        let mut stretched = pos.clone();
+        stretched[0] = far;
+        bonds.compute_forces(&stretched, &mut acc, &region);
+        assert_eq!(Real::INFINITY, PotentialEnergy::<3>::u_sum(&bonds));
+    }
+
+    #[test]
+    fn angles() {
+        check(&Angles::harmonic(20., 1.9));
+        check(&Angles::cosine(5.));
+    }
+
+    #[test]
+    fn dihedrals() {
+        check(&Dihedrals::proper(3., 3, 0.));
+        check(&Dihedrals::proper(2., 1, 1.));
+        check(&Dihedrals::improper(4., 2, 0.5));
+    }
+
+    #[test]
+    fn trans_is_lowest_for_no_phase() {
+        let pos = [[1., 1., 0.], [0., 0., 0.], [0., 0., 1.], [-1., -1., 1.]].map(DVector::from);
+        let state = State::default();
+        for x in pos.iter() {
+            state.insert_atom(x.clone());
+        }
+        *state.get_topology() = Topology::default().chain(0..4);
+        let dihedrals = Dihedrals::proper(1., 1, 0.);
+        dihedrals.attach(&state);
+        let mut acc = vec![DVector::default(); 4];
+        dihedrals.compute_forces(&pos, &mut acc, &Region::new([10.; 3]));
+        assert!(dihedrals.u_sum().abs() < 1e-6);
+    }
+
+    #[test]
+    fn unequal_masses_keep_their_centre() {
+        use crate::integrator::{Integrator, Leapfrog};
+
+        let state = State::default();
+        state.insert_atom(DVector::from([-0.4, 0., 0.]));
+        state.insert_atom(DVector::from([0.4, 0., 0.]));
+        state.get_species()[1] = 1;
+        *state.get_masses() = vec![1., 3.];
+        *state.get_topology() = Topology::default().bond(0, 1);
+        let bonds = Bonds::harmonic(100., 0.5);
+        bonds.attach(&state);
+        let region = Region::new([10.; 3]);
+        let centre = |state: &State<3>| {
+            let pos = state.get_pos();
+            (&pos[0] + &(3. * &pos[1])).components()[0] / 4.
+        };
+        let start = centre(&state);
+        let integrator = Leapfrog;
+        integrator.prime(&state, &region, &bonds);
+        for _ in 0..500 {
+            integrator
+                .single_step(1e-3, &state, &region, &bonds)
+                .unwrap();
+            assert!((centre(&state) - start).abs() < 1e-5);
+        }
+        let vel = state.get_vel();
+        assert!(vel[0].length() > 0.1);
+        assert!((&vel[0] + &(3. * &vel[1])).length() < 1e-4);
+    }
+}
@@ -2,13 +2,56 @@
 
 use crate::verlet;
 use d_vector::{DVector, Real};
-use std::{
-    fmt::Debug,
-    ops::{AddAssign, SubAssign},
-};
+use std::fmt::Debug;
 
-pub trait BoundaryConditions<const D: usize>: Debug {
-    fn wrap(&self, pos: &mut DVector<D>);
+pub trait BoundaryConditions<const D: usize>: Debug + Sync {
+    /// Brings a position back into the box along periodic axes,
+    /// however far outside it is. Returns how many box lengths were
+    /// taken off along each axis: the image the position was in.
+    fn wrap(&self, pos: &mut DVector<D>) -> [i32; D];
+    /// Replaces a separation between two atoms with its shortest periodic image.
+    fn minimum_image(&self, dr: &mut DVector<D>) {
+        self.wrap(dr);
+    }
+    /// Brings an atom that has just moved back into the box,
+    /// changing its velocity if it bounced off a wall. Returns the image as `wrap` does.
+    fn confine(&self, position: &mut DVector<D>, velocity: &mut DVector<D>) -> [i32; D] {
+        self.wrap(position)
+    }
+    /// Position of an atom in image `image` of the box, undoing `wrap`.
+    fn unwrap(&self, position: &DVector<D>, image: &[i32; D]) -> DVector<D> {
+        match self.box_dimensions() {
+            Some(dimensions) => {
+                let mut x = *position.components();
+                for ((x, n), length) in x.iter_mut().zip(image).zip(dimensions) {
+                    *x += *n as Real * length;
+                }
+                DVector::from(x)
+            }
+            None => position.clone(),
+        }
+    }
+    fn volume(&self) -> Real;
+    /// Position at fractional coordinates `fractional` of the box or cell;
+    /// coordinates in `[-1/2, 1/2)` give a point inside it.
+    fn at_fractional(&self, fractional: &[Real; D]) -> DVector<D>;
+    /// Stretches the box by `factor` along every axis.
+    fn scale(&mut self, factor: Real);
+    /// Edge lengths of the axis-aligned box the atoms live in, if there is one.
+    fn box_dimensions(&self) -> Option<&[Real; D]> {
+        None
+    }
+    /// Rate at which the images above the box slide along the first axis,
+    /// relative to the box height along the second one.
+    fn shear_rate(&self) -> Real {
+        0.
+    }
+    /// How far the images above the box have slid along the first axis by now.
+    fn shear_offset(&self) -> Real {
+        0.
+    }
+    /// Brings time-dependent boundaries up to `time_now`; `Job::run` calls it every step.
+    fn sync(&mut self, time_now: Real) {}
 }
 
 #[derive(Debug)]
@@ -23,34 +66,308 @@ impl<const D: usize> Region<D> {
         }
     }
 
-    fn is_above(&self, position: &DVector<D>, index: usize) -> bool {
-        position.components()[index] >= self.inner.components()[index] / 2.
+    pub fn dimensions(&self) -> &[Real; D] {
+        self.inner.components()
+    }
+}
+
+impl<const D: usize> BoundaryConditions<D> for Region<D> {
+    fn wrap(&self, position: &mut DVector<D>) -> [i32; D] {
+        let mut x = *position.components();
+        let mut image = [0; D];
+        for ((x, n), length) in x.iter_mut().zip(image.iter_mut()).zip(self.dimensions()) {
+            (*x, *n) = fold(*x, *length);
+        }
+        *position = DVector::from(x);
+        image
+    }
+
+    fn minimum_image(&self, dr: &mut DVector<D>) {
+        let mut x = *dr.components();
+        for (x, length) in x.iter_mut().zip(self.dimensions()) {
+            *x = nearest_image(*x, *length);
+        }
+        *dr = DVector::from(x);
+    }
+
+    fn volume(&self) -> Real {
+        self.dimensions().iter().product()
+    }
+
+    fn at_fractional(&self, fractional: &[Real; D]) -> DVector<D> {
+        scale_by(fractional, self.dimensions())
+    }
+
+    fn scale(&mut self, factor: Real) {
+        self.inner = factor * &self.inner;
+    }
+
+    fn box_dimensions(&self) -> Option<&[Real; D]> {
+        Some(self.dimensions())
+    }
+}
+
+fn scale_by<const D: usize>(fractional: &[Real; D], dimensions: &[Real; D]) -> DVector<D> {
+    let mut x = *fractional;
+    for (x, length) in x.iter_mut().zip(dimensions) {
+        *x *= length;
+    }
+    DVector::from(x)
+}
+
+/// Periodic image of `x` in `[-length / 2, length / 2)`
+/// and the number of lengths taken off to get there.
+pub(crate) fn fold(x: Real, length: Real) -> (Real, i32) {
+    let n = (x / length + 0.5).floor();
+    let folded = x - length * n;
+    // Rounding may land an atom just below the lower face exactly on the upper one.
+    if folded >= length / 2. {
+        (folded - length, n as i32 + 1)
+    } else {
+        (folded, n as i32)
+    }
+}
+
+/// Periodic image of `x` closest to zero.
+pub(crate) fn nearest_image(x: Real, length: Real) -> Real {
+    x - length * (x / length).round()
+}
+
+/// What happens to an atom crossing a face of `Walls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Comes back through the opposite face, as in `Region`.
+    Periodic,
+    /// Bounces elastically: the normal velocity component is reversed.
+    Reflecting,
+}
+
+/// A box whose faces are reflecting walls or periodic, chosen per axis,
+/// e.g. walls in z and periodicity in x and y for a slab.
+#[derive(Debug)]
+pub struct Walls<const D: usize> {
+    inner: DVector<D>,
+    edges: [Edge; D],
+}
+
+impl<const D: usize> Walls<D> {
+    /// Reflecting walls along every axis.
+    pub fn new(dimensions: [Real; D]) -> Self {
+        Self {
+            inner: DVector::from(dimensions),
+            edges: [Edge::Reflecting; D],
+        }
     }
 
-    fn is_below(&self, position: &DVector<D>, index: usize) -> bool {
-        position.components()[index] < -self.inner.components()[index] / 2.
+    /// Makes the faces across `axis` periodic.
+    pub fn periodic(mut self, axis: usize) -> Self {
+        self.edges[axis] = Edge::Periodic;
+        self
     }
 
     pub fn dimensions(&self) -> &[Real; D] {
         self.inner.components()
     }
+
+    pub fn edges(&self) -> &[Edge; D] {
+        &self.edges
+    }
 }
 
-impl<const D: usize> BoundaryConditions<D> for Region<D> {
-    fn wrap(&self, position: &mut DVector<D>) {
-        let mut shift = [0 as Real; D];
-        for (i, s) in shift.iter_mut().enumerate() {
-            if self.is_above(position, i) {
-                s.sub_assign(self.inner.components()[i]);
-            } else if self.is_below(position, i) {
-                s.add_assign(self.inner.components()[i]);
+impl<const D: usize> BoundaryConditions<D> for Walls<D> {
+    fn wrap(&self, pos: &mut DVector<D>) -> [i32; D] {
+        let mut x = *pos.components();
+        let mut image = [0; D];
+        for (i, (x, n)) in x.iter_mut().zip(image.iter_mut()).enumerate() {
+            if self.edges[i] == Edge::Periodic {
+                (*x, *n) = fold(*x, self.dimensions()[i]);
+            }
+        }
+        *pos = DVector::from(x);
+        image
+    }
+
+    fn minimum_image(&self, dr: &mut DVector<D>) {
+        let mut x = *dr.components();
+        for ((x, edge), length) in x.iter_mut().zip(self.edges).zip(self.dimensions()) {
+            if edge == Edge::Periodic {
+                *x = nearest_image(*x, *length);
+            }
+        }
+        *dr = DVector::from(x);
+    }
+
+    fn confine(&self, position: &mut DVector<D>, velocity: &mut DVector<D>) -> [i32; D] {
+        let image = self.wrap(position);
+        let mut x = *position.components();
+        let mut v = *velocity.components();
+        for i in 0..D {
+            let half = self.dimensions()[i] / 2.;
+            if self.edges[i] == Edge::Reflecting && x[i].abs() > half {
+                x[i] = 2. * half.copysign(x[i]) - x[i];
+                v[i] = -v[i];
             }
         }
-        position.add_assign(DVector::from(shift));
+        *position = DVector::from(x);
+        *velocity = DVector::from(v);
+        image
+    }
+
+    fn volume(&self) -> Real {
+        self.dimensions().iter().product()
+    }
+
+    fn at_fractional(&self, fractional: &[Real; D]) -> DVector<D> {
+        scale_by(fractional, self.dimensions())
+    }
+
+    fn scale(&mut self, factor: Real) {
+        self.inner = factor * &self.inner;
+    }
+
+    fn box_dimensions(&self) -> Option<&[Real; D]> {
+        Some(self.dimensions())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn bounce_off_wall() {
+        let slab = Walls::new([4., 4., 2.]).periodic(0).periodic(1);
+        let mut position = DVector::from([2.5, 0., 1.25]);
+        let mut velocity = DVector::from([1., 1., 2.]);
+        slab.confine(&mut position, &mut velocity);
+        assert_eq!(&[-1.5, 0., 0.75], position.components());
+        assert_eq!(&[1., 1., -2.], velocity.components());
+
+        let mut separation = DVector::from([3., 0., 1.5]);
+        slab.minimum_image(&mut separation);
+        assert_eq!(&[-1., 0., 1.5], separation.components());
+    }
+
+    #[test]
+    fn gas_stays_inside_walls() {
+        use crate::{initial_state::randomize_vectors, potential::NoInteraction};
+
+        let walls = Walls::new([3., 3.]);
+        let mut pos = vec![DVector::default(); 20];
+        let mut vel = vec![DVector::default(); 20];
+        let mut acc = vec![DVector::default(); 20];
+        let mut images = vec![[0; 2]; 20];
+        randomize_vectors(&mut vel, 1.);
+        let speeds: Vec<Real> = vel.iter().map(|v| v.length()).collect();
+        for _ in 0..1000 {
+            verlet::single_step(
+                0.01,
+                &mut pos,
+                &mut vel,
+                &mut acc,
+                &mut images,
+                &[1.; 20],
+                &walls,
+                &NoInteraction,
+            );
+        }
+        assert!(images.iter().all(|image| *image == [0; 2]));
+        for (position, (velocity, speed)) in pos.iter().zip(vel.iter().zip(speeds)) {
+            assert!(position.components().iter().all(|x| x.abs() <= 1.5));
+            assert!((velocity.length() - speed).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn unwrap_follows_free_flight() {
+        use crate::{initial_state::randomize_vectors, potential::NoInteraction};
+
+        let region = Region::new([2., 3., 4.]);
+        let mut pos = vec![DVector::default(); 10];
+        let mut vel = vec![DVector::default(); 10];
+        let mut acc = vec![DVector::default(); 10];
+        let mut images = vec![[0; 3]; 10];
+        randomize_vectors(&mut vel, 5.);
+        for _ in 0..1000 {
+            verlet::single_step(
+                0.01,
+                &mut pos,
+                &mut vel,
+                &mut acc,
+                &mut images,
+                &[1.; 10],
+                &region,
+                &NoInteraction,
+            );
+        }
+        assert!(images.iter().any(|image| *image != [0; 3]));
+        for ((position, velocity), image) in pos.iter().zip(vel.iter()).zip(images.iter()) {
+            let travelled = region.unwrap(position, image);
+            assert!((&travelled - &(10. * velocity)).length() < 1e-3);
+        }
+    }
+
+    /// Integer number of `length`s that `a` and `b` differ by.
+    fn whole_boxes(a: Real, b: Real, length: Real) -> bool {
+        let n = (a - b) / length;
+        (n - n.round()).abs() < 1e-3
+    }
+
+    proptest! {
+        #[test]
+        fn wrap_folds_any_position(
+            dimensions in prop::array::uniform3(0.5 as Real..20.),
+            x in prop::array::uniform3(-1000 as Real..1000.),
+        ) {
+            let region = Region::new(dimensions);
+            let mut position = DVector::from(x);
+            let image = region.wrap(&mut position);
+            for ((w, x), length) in position.components().iter().zip(x).zip(dimensions) {
+                prop_assert!(*w >= -length / 2. && *w < length / 2.);
+                prop_assert!(whole_boxes(*w, x, length));
+            }
+            let unwrapped = region.unwrap(&position, &image);
+            for (u, x) in unwrapped.components().iter().zip(x) {
+                prop_assert!((u - x).abs() < 1e-3 * (1. + x.abs()));
+            }
+        }
+
+        #[test]
+        fn minimum_image_is_shortest(
+            dimensions in prop::array::uniform3(0.5 as Real..20.),
+            a in prop::array::uniform3(-1000 as Real..1000.),
+            b in prop::array::uniform3(-1000 as Real..1000.),
+        ) {
+            let region = Region::new(dimensions);
+            let mut dr = &DVector::from(a) - &DVector::from(b);
+            let unwrapped = dr.clone();
+            region.minimum_image(&mut dr);
+
+            let (mut a, mut b) = (DVector::from(a), DVector::from(b));
+            region.wrap(&mut a);
+            region.wrap(&mut b);
+            let mut folded = &a - &b;
+            region.minimum_image(&mut folded);
+
+            for (i, length) in dimensions.into_iter().enumerate() {
+                let d = dr.components()[i];
+                prop_assert!(d.abs() <= length / 2. * (1. + 1e-4));
+                prop_assert!(whole_boxes(d, unwrapped.components()[i], length));
+                prop_assert!(whole_boxes(d, folded.components()[i], length));
+                prop_assert!(d.abs() - folded.components()[i].abs() < 1e-3);
+            }
+        }
+
+        #[test]
+        fn walls_keep_reflecting_axes(
+            x in prop::array::uniform3(-100 as Real..100.),
+        ) {
+            let slab = Walls::new([4., 4., 2.]).periodic(0);
+            let mut dr = DVector::from(x);
+            slab.minimum_image(&mut dr);
+            prop_assert!(dr.components()[0].abs() <= 2. * (1. + 1e-4));
+            prop_assert_eq!(&x[1..], &dr.components()[1..]);
+        }
+    }
 }
@@ -0,0 +1,213 @@
+#![allow(unused, dead_code)]
+
+use d_vector::{DVector, Real};
+
+/// Atoms binned into a periodic grid of cells, each at least `min_size` wide.
+/// Pairs are only looked for in the same and adjacent cells.
+#[derive(Debug)]
+pub struct CellList<const D: usize> {
+    cells: [usize; D],
+    bins: Vec<Vec<usize>>,
+    atom_cells: Vec<usize>,
+    offsets: Vec<[isize; D]>,
+}
+
+impl<const D: usize> CellList<D> {
+    /// Returns `None` when the box holds fewer than three cells along some axis:
+    /// adjacent cells are no longer distinct then and pairs would be counted twice.
+    pub fn new(pos: &[DVector<D>], dimensions: &[Real; D], min_size: Real) -> Option<Self> {
+        let mut cells = [0; D];
+        for (cell, dimension) in cells.iter_mut().zip(dimensions.iter()) {
+            *cell = (dimension / min_size).floor() as usize;
+            if *cell < 3 {
+                return None;
+            }
+        }
+        let mut list = Self {
+            cells,
+            bins: vec![Vec::new(); cells.iter().product()],
+            atom_cells: Vec::with_capacity(pos.len()),
+            offsets: half_shell(),
+        };
+        for (index, position) in pos.iter().enumerate() {
+            let cell = list.cell_of(position, dimensions);
+            list.bins[cell].push(index);
+            list.atom_cells.push(cell);
+        }
+        Some(list)
+    }
+
+    pub fn n_cells(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn for_each_pair(&self, mut f: impl FnMut(usize, usize)) {
+        for cell in 0..self.n_cells() {
+            self.for_each_pair_from(cell, &mut f);
+        }
+    }
+
+    /// Visits pairs inside `cell` and between `cell` and half of its neighbours,
+    /// so that running over every cell visits each pair exactly once.
+    pub fn for_each_pair_from(&self, cell: usize, f: &mut impl FnMut(usize, usize)) {
+        let atoms = &self.bins[cell];
+        for (k, &j1) in atoms.iter().enumerate() {
+            for &j2 in &atoms[(k + 1)..] {
+                f(j1, j2);
+            }
+        }
+        let coords = self.coords(cell);
+        for offset in self.offsets.iter() {
+            let neighbour = self.shifted(&coords, offset);
+            for &j1 in atoms.iter() {
+                for &j2 in self.bins[neighbour].iter() {
+                    f(j1, j2);
+                }
+            }
+        }
+    }
+
+    /// Visits every atom in the cell of `atom` and in all cells around it,
+    /// `atom` itself included.
+    pub fn for_each_neighbour(&self, atom: usize, mut f: impl FnMut(usize)) {
+        let cell = self.atom_cells[atom];
+        let coords = self.coords(cell);
+        let shell = std::iter::once(cell).chain(self.offsets.iter().flat_map(|offset| {
+            let opposite = offset.map(|o| -o);
+            [
+                self.shifted(&coords, offset),
+                self.shifted(&coords, &opposite),
+            ]
+        }));
+        for neighbour in shell {
+            for &j in self.bins[neighbour].iter() {
+                f(j);
+            }
+        }
+    }
+
+    fn cell_of(&self, position: &DVector<D>, dimensions: &[Real; D]) -> usize {
+        let mut coords = [0; D];
+        for (i, c) in coords.iter_mut().enumerate() {
+            let fraction = position.components()[i] / dimensions[i] + 0.5;
+            let n = self.cells[i] as isize;
+            *c = ((fraction * n as Real).floor() as isize).rem_euclid(n) as usize;
+        }
+        self.index(&coords)
+    }
+
+    fn coords(&self, mut cell: usize) -> [usize; D] {
+        let mut coords = [0; D];
+        for i in (0..D).rev() {
+            coords[i] = cell % self.cells[i];
+            cell /= self.cells[i];
+        }
+        coords
+    }
+
+    fn index(&self, coords: &[usize; D]) -> usize {
+        coords
+            .iter()
+            .zip(self.cells.iter())
+            .fold(0, |index, (c, n)| index * n + c)
+    }
+
+    fn shifted(&self, coords: &[usize; D], offset: &[isize; D]) -> usize {
+        let mut shifted = [0; D];
+        for (i, s) in shifted.iter_mut().enumerate() {
+            let n = self.cells[i] as isize;
+            *s = (coords[i] as isize + offset[i]).rem_euclid(n) as usize;
+        }
+        self.index(&shifted)
+    }
+}
+
+/// Visits every pair of `n_mol` atoms once: the brute-force counterpart of `CellList`.
+pub fn for_each_pair(n_mol: usize, mut f: impl FnMut(usize, usize)) {
+    for j1 in 0..n_mol {
+        for j2 in (j1 + 1)..n_mol {
+            f(j1, j2);
+        }
+    }
+}
+
+/// Offsets to the neighbouring cells whose first non-zero component is positive.
+fn half_shell<const D: usize>() -> Vec<[isize; D]> {
+    let mut offsets = Vec::new();
+    for mut code in 0..3_usize.pow(D as u32) {
+        let mut offset = [0; D];
+        for o in offset.iter_mut() {
+            *o = (code % 3) as isize - 1;
+            code /= 3;
+        }
+        if offset.iter().find(|o| **o != 0).is_some_and(|o| *o > 0) {
+            offsets.push(offset);
+        }
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boundaries::{BoundaryConditions, Region},
+        initial_state::cubic_lattice,
+    };
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn half_shell_size() {
+        assert_eq!(4, half_shell::<2>().len());
+        assert_eq!(13, half_shell::<3>().len());
+    }
+
+    #[test]
+    fn too_small_box() {
+        let (region, pos) = cubic_lattice::<3>(27, 0.8);
+        assert!(CellList::new(&pos, region.dimensions(), 2.5).is_none());
+    }
+
+    #[test]
+    fn same_pairs_as_brute_force() {
+        let r_cut = 2.5;
+        let (region, mut pos) = cubic_lattice::<3>(1000, 0.8);
+        for position in pos.iter_mut() {
+            *position += 0.3 * DVector::random_vector();
+            region.wrap(position);
+        }
+        let within_cut = |j1: usize, j2: usize| {
+            let mut dr = &pos[j1] - &pos[j2];
+            region.minimum_image(&mut dr);
+            dr.square_length() < r_cut * r_cut
+        };
+
+        let mut expected = BTreeSet::new();
+        for j1 in 0..pos.len() {
+            for j2 in (j1 + 1)..pos.len() {
+                if within_cut(j1, j2) {
+                    expected.insert((j1, j2));
+                }
+            }
+        }
+
+        let cells = CellList::new(&pos, region.dimensions(), r_cut).unwrap();
+        let mut found = BTreeSet::new();
+        cells.for_each_pair(|j1, j2| {
+            if within_cut(j1, j2) {
+                assert!(found.insert((j1.min(j2), j1.max(j2))), "pair visited twice");
+            }
+        });
+        assert_eq!(expected, found);
+
+        let mut around = BTreeSet::new();
+        for j1 in 0..pos.len() {
+            cells.for_each_neighbour(j1, |j2| {
+                if j1 < j2 && within_cut(j1, j2) {
+                    assert!(around.insert((j1, j2)), "neighbour visited twice");
+                }
+            });
+        }
+        assert_eq!(expected, around);
+    }
+}
@@ -0,0 +1,489 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    integrator::{Integrator, StepError},
+    potential::PotentialEnergy,
+    state::MolecularState,
+    topology::Constraint,
+    verlet,
+};
+use d_vector::{DVector, Real};
+use std::{cell::RefCell, fmt};
+
+/// Iteration over the constraints that did not bring one of them within tolerance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConstraintError {
+    pub constraint: usize,
+    pub atoms: [usize; 2],
+    /// Relative deviation left: of the squared length for positions,
+    /// of the relative velocity along the bond for velocities.
+    pub deviation: Real,
+    pub iterations: usize,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "constraint {} between atoms {} and {} is off by {} after {} iterations; \
+             the time step may be too large",
+            self.constraint, self.atoms[0], self.atoms[1], self.deviation, self.iterations
+        )
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+/// `Leapfrog` with the constraints of the topology kept by SHAKE: after the
+/// drift, positions are moved back along the bonds they had before it until
+/// every length is within `tolerance`, and the half-step velocities with them;
+/// lighter atoms move more. The step fails with a `ConstraintError` if
+/// `max_iterations` sweeps over the constraints are not enough.
+#[derive(Debug)]
+pub struct Shake {
+    tolerance: Real,
+    max_iterations: usize,
+    virial: RefCell<Vec<Real>>,
+}
+
+/// Velocity Verlet with the constraints of the topology kept by RATTLE: SHAKE
+/// on the positions, then the velocities at the end of the step are freed
+/// of any component along the bonds. The virial is that of the velocity stage.
+#[derive(Debug, Default)]
+pub struct Rattle {
+    shake: Shake,
+}
+
+impl Default for Shake {
+    fn default() -> Self {
+        Self {
+            tolerance: 1e-5,
+            max_iterations: 500,
+            virial: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl Shake {
+    pub fn tolerance(mut self, tolerance: Real) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Drift with SHAKE, boundaries and forces of one step, but no closing kick.
+    fn advance<const D: usize>(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let constraints = state.get_topology().constraints.clone();
+        let masses = state.atom_masses();
+        let mut pos = state.get_pos();
+        let mut vel = state.get_vel();
+        let mut acc = state.get_acc();
+        let before = pos.clone();
+        verlet::leapfrog_begin(delta_t, &mut pos, &mut vel, &acc);
+        let virial = shake(
+            &constraints,
+            &before,
+            &mut pos,
+            &mut vel,
+            &masses,
+            delta_t,
+            self.tolerance,
+            self.max_iterations,
+            boundaries,
+        )?;
+        self.store(virial);
+        verlet::apply_boundary_conditions(boundaries, &mut pos, &mut vel, &mut state.get_images());
+        verlet::compute_accelerations(potential_energy, &pos, &mut acc, &masses, boundaries);
+        Ok(())
+    }
+
+    fn store<const D: usize>(&self, virial: [[Real; D]; D]) {
+        *self.virial.borrow_mut() = virial.into_iter().flatten().collect();
+    }
+}
+
+impl Rattle {
+    pub fn tolerance(mut self, tolerance: Real) -> Self {
+        self.shake.tolerance = tolerance;
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.shake.max_iterations = max_iterations;
+        self
+    }
+}
+
+impl<const D: usize> Integrator<D> for Shake {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        self.advance(delta_t, state, boundaries, potential_energy)?;
+        verlet::leapfrog_end(delta_t, &mut state.get_vel(), &state.get_acc());
+        Ok(())
+    }
+
+    fn constraint_virial(&self) -> [[Real; D]; D] {
+        let mut tensor = [[0.; D]; D];
+        for (row, w) in tensor.iter_mut().zip(self.virial.borrow().chunks_exact(D)) {
+            row.copy_from_slice(w);
+        }
+        tensor
+    }
+}
+
+impl<const D: usize> Integrator<D> for Rattle {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let masses = state.atom_masses();
+        self.shake
+            .advance(delta_t, state, boundaries, potential_energy)?;
+        let mut vel = state.get_vel();
+        verlet::leapfrog_end(delta_t, &mut vel, &state.get_acc());
+        let virial = rattle(
+            &state.get_topology().constraints,
+            &state.get_pos(),
+            &mut vel,
+            &masses,
+            delta_t,
+            self.shake.tolerance,
+            self.shake.max_iterations,
+            boundaries,
+        )?;
+        self.shake.store(virial);
+        Ok(())
+    }
+
+    fn constraint_virial(&self) -> [[Real; D]; D] {
+        Integrator::<D>::constraint_virial(&self.shake)
+    }
+}
+
+/// Moves the atoms of every constraint along the bond it had at `before` until
+/// the squared lengths at `pos` are within `tolerance` of their targets, and
+/// changes the velocities that drifted the atoms there to match, each atom
+/// by the inverse of its mass. Returns the virial tensor of the constraint
+/// forces this takes.
+#[allow(clippy::too_many_arguments)]
+pub fn shake<const D: usize>(
+    constraints: &[Constraint],
+    before: &[DVector<D>],
+    pos: &mut [DVector<D>],
+    vel: &mut [DVector<D>],
+    masses: &[Real],
+    delta_t: Real,
+    tolerance: Real,
+    max_iterations: usize,
+    boundaries: &dyn BoundaryConditions<D>,
+) -> Result<[[Real; D]; D], ConstraintError> {
+    let bonds: Vec<DVector<D>> = constraints
+        .iter()
+        .map(|c| separation(before, c, boundaries))
+        .collect();
+    let mut multipliers = vec![0 as Real; constraints.len()];
+    let mut worst = None;
+    for iteration in 0..max_iterations {
+        worst = None;
+        for (n, (constraint, bond)) in constraints.iter().zip(bonds.iter()).enumerate() {
+            let dd = constraint.length * constraint.length;
+            let s = separation(pos, constraint, boundaries);
+            let deviation = (dd - s.square_length()) / (2. * dd);
+            if deviation.abs() <= tolerance {
+                continue;
+            }
+            worst = Some(error(n, constraint, deviation, iteration + 1, worst));
+            let alignment = &s * bond;
+            if alignment <= 0. {
+                return Err(error(n, constraint, deviation, iteration + 1, None));
+            }
+            let [i, j] = constraint.atoms;
+            let (w_i, w_j) = (1. / masses[i], 1. / masses[j]);
+            let g = deviation * dd / ((w_i + w_j) * alignment);
+            pos[i] += &(w_i * g * bond);
+            pos[j] -= &(w_j * g * bond);
+            vel[i] += &(w_i * g / delta_t * bond);
+            vel[j] -= &(w_j * g / delta_t * bond);
+            multipliers[n] += g;
+        }
+        if worst.is_none() {
+            return Ok(virial(&bonds, &multipliers, 2. / (delta_t * delta_t)));
+        }
+    }
+    Err(worst.unwrap())
+}
+
+/// Removes the relative velocity along the bond of every constraint at `pos`
+/// until it is within `tolerance` of the length over `delta_t`. Returns the
+/// virial tensor of the constraint forces of the closing half kick.
+#[allow(clippy::too_many_arguments)]
+pub fn rattle<const D: usize>(
+    constraints: &[Constraint],
+    pos: &[DVector<D>],
+    vel: &mut [DVector<D>],
+    masses: &[Real],
+    delta_t: Real,
+    tolerance: Real,
+    max_iterations: usize,
+    boundaries: &dyn BoundaryConditions<D>,
+) -> Result<[[Real; D]; D], ConstraintError> {
+    let bonds: Vec<DVector<D>> = constraints
+        .iter()
+        .map(|c| separation(pos, c, boundaries))
+        .collect();
+    let mut multipliers = vec![0 as Real; constraints.len()];
+    let mut worst = None;
+    for iteration in 0..max_iterations {
+        worst = None;
+        for (n, (constraint, bond)) in constraints.iter().zip(bonds.iter()).enumerate() {
+            let [i, j] = constraint.atoms;
+            let dd = bond.square_length();
+            let rv = bond * &(&vel[i] - &vel[j]);
+            let deviation = rv * delta_t / dd;
+            if deviation.abs() <= tolerance {
+                continue;
+            }
+            worst = Some(error(n, constraint, deviation, iteration + 1, worst));
+            let (w_i, w_j) = (1. / masses[i], 1. / masses[j]);
+            let k = -rv / ((w_i + w_j) * dd);
+            vel[i] += &(w_i * k * bond);
+            vel[j] -= &(w_j * k * bond);
+            multipliers[n] += k;
+        }
+        if worst.is_none() {
+            return Ok(virial(&bonds, &multipliers, 2. / delta_t));
+        }
+    }
+    Err(worst.unwrap())
+}
+
+fn separation<const D: usize>(
+    pos: &[DVector<D>],
+    constraint: &Constraint,
+    boundaries: &dyn BoundaryConditions<D>,
+) -> DVector<D> {
+    let [i, j] = constraint.atoms;
+    let mut dr = &pos[i] - &pos[j];
+    boundaries.minimum_image(&mut dr);
+    dr
+}
+
+/// The larger of `worst` and the deviation of constraint `n`.
+fn error(
+    n: usize,
+    constraint: &Constraint,
+    deviation: Real,
+    iterations: usize,
+    worst: Option<ConstraintError>,
+) -> ConstraintError {
+    let this = ConstraintError {
+        constraint: n,
+        atoms: constraint.atoms,
+        deviation: deviation.abs(),
+        iterations,
+    };
+    match worst {
+        Some(worst) if worst.deviation >= this.deviation => worst,
+        _ => this,
+    }
+}
+
+/// `Σ bond ⊗ bond` weighted by the multipliers scaled to forces by `factor`.
+fn virial<const D: usize>(
+    bonds: &[DVector<D>],
+    multipliers: &[Real],
+    factor: Real,
+) -> [[Real; D]; D] {
+    let mut tensor = [[0.; D]; D];
+    for (bond, g) in bonds.iter().zip(multipliers) {
+        let x = bond.components();
+        for (row, a) in tensor.iter_mut().zip(x) {
+            for (w, b) in row.iter_mut().zip(x) {
+                *w += factor * g * (a * b);
+            }
+        }
+    }
+    tensor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boundaries::Region, lennard_jones::LennardJones, state::State, topology::Topology,
+    };
+
+    /// Rigid dimers on a lattice, each bond straddling the box edge for some.
+    fn dimers(n: usize, length: Real) -> (Region<3>, State<3>) {
+        let size = 2. * n as Real;
+        let state = State::default();
+        let mut topology = Topology::default();
+        for c in 0..n * n * n {
+            let x = [c / (n * n), c / n % n, c % n].map(|c| 2. * c as Real - size / 2. + 0.3);
+            let a = DVector::from(x);
+            let b = &a - &DVector::from([length, 0., 0.]);
+            state.insert_atom(a);
+            state.insert_atom(b);
+            topology = topology.constraint(2 * c, 2 * c + 1, length);
+        }
+        let region = Region::new([size; 3]);
+        let mut pos = state.get_pos();
+        let mut vel = state.get_vel();
+        crate::initial_state::randomize_vectors(&mut vel, 1.);
+        verlet::apply_boundary_conditions(&region, &mut pos, &mut vel, &mut state.get_images());
+        drop((pos, vel));
+        *state.get_topology() = topology;
+        (region, state)
+    }
+
+    fn deviations(state: &State<3>, region: &Region<3>) -> (Real, Real) {
+        let pos = state.get_pos();
+        let vel = state.get_vel();
+        let (mut length, mut velocity) = (0 as Real, 0 as Real);
+        for c in state.get_topology().constraints.iter() {
+            let bond = separation(&pos, c, region);
+            let [i, j] = c.atoms;
+            length = length.max((bond.length() - c.length).abs());
+            velocity = velocity.max((&bond * &(&vel[i] - &vel[j])).abs());
+        }
+        (length, velocity)
+    }
+
+    #[test]
+    fn shake_keeps_bond_lengths() {
+        let (region, state) = dimers(3, 1.);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let shake = Shake::default().tolerance(1e-6);
+        shake.prime(&state, &region, &wca);
+        for _ in 0..200 {
+            shake.single_step(0.005, &state, &region, &wca).unwrap();
+        }
+        assert!(deviations(&state, &region).0 < 1e-4);
+        assert!(Integrator::<3>::constraint_virial(&shake)
+            .iter()
+            .flatten()
+            .any(|w| *w != 0.));
+    }
+
+    #[test]
+    fn rattle_keeps_bond_lengths_and_velocities() {
+        let (region, state) = dimers(3, 1.);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let rattle = Rattle::default().tolerance(1e-6);
+        rattle.prime(&state, &region, &wca);
+        for _ in 0..200 {
+            rattle.single_step(0.005, &state, &region, &wca).unwrap();
+        }
+        let (length, velocity) = deviations(&state, &region);
+        assert!(length < 1e-4);
+        assert!(velocity < 1e-4);
+    }
+
+    #[test]
+    fn rigid_rotor_virial_balances_centrifugal_force() {
+        // A free dimer spinning at angular velocity ω needs a tension of ω²d/2
+        // on each atom, so the constraint virial is -ω²d²/2.
+        let state = State::<3>::default();
+        state.insert_atom(DVector::from([0.5, 0., 0.]));
+        state.insert_atom(DVector::from([-0.5, 0., 0.]));
+        *state.get_vel() = vec![DVector::from([0., 1., 0.]), DVector::from([0., -1., 0.])];
+        *state.get_topology() = Topology::default().constraint(0, 1, 1.);
+        let region = Region::new([10.; 3]);
+        let rattle = Rattle::default().tolerance(1e-7);
+        let potential = crate::potential::NoInteraction;
+        rattle.prime(&state, &region, &potential);
+        for _ in 0..100 {
+            rattle
+                .single_step(0.01, &state, &region, &potential)
+                .unwrap();
+        }
+        let virial: Real = (0..3)
+            .map(|a| Integrator::<3>::constraint_virial(&rattle)[a][a])
+            .sum();
+        assert!((virial + 2.).abs() < 1e-2, "{}", virial);
+    }
+
+    #[test]
+    fn unequal_rotor_turns_about_its_centre_of_mass() {
+        // Masses 1 and 3 a unit apart turn about a point 3/4 from the light one;
+        // at ω = 2 the tension is 1 · ω² · 3/4 = 3, and so is minus the virial.
+        let state = State::<3>::default();
+        state.insert_atom(DVector::from([0.75, 0., 0.]));
+        state.insert_atom(DVector::from([-0.25, 0., 0.]));
+        state.get_species()[1] = 1;
+        *state.get_masses() = vec![1., 3.];
+        *state.get_vel() = vec![DVector::from([0., 1.5, 0.]), DVector::from([0., -0.5, 0.])];
+        *state.get_topology() = Topology::default().constraint(0, 1, 1.);
+        let region = Region::new([10.; 3]);
+        let rattle = Rattle::default().tolerance(1e-7);
+        let potential = crate::potential::NoInteraction;
+        rattle.prime(&state, &region, &potential);
+        for _ in 0..100 {
+            rattle
+                .single_step(0.01, &state, &region, &potential)
+                .unwrap();
+            let pos = state.get_pos();
+            assert!((&pos[0] + &(3. * &pos[1])).length() < 1e-4);
+        }
+        let virial: Real = (0..3)
+            .map(|a| Integrator::<3>::constraint_virial(&rattle)[a][a])
+            .sum();
+        assert!((virial + 3.).abs() < 1e-2, "{}", virial);
+        assert!(deviations(&state, &region).1 < 1e-5);
+    }
+
+    #[test]
+    fn failure_is_reported() {
+        let (region, state) = dimers(2, 1.);
+        let mut pos = state.get_pos().clone();
+        let before = pos.clone();
+        let mut vel = state.get_vel().clone();
+        pos[0] += &DVector::from([0.4, 0.3, 0.]);
+        let constraints = state.get_topology().constraints.clone();
+        let result = shake(
+            &constraints,
+            &before,
+            &mut pos,
+            &mut vel,
+            &[1.; 16],
+            0.005,
+            1e-6,
+            1,
+            &region,
+        );
+        let error = result.unwrap_err();
+        assert_eq!([0, 1], error.atoms);
+        assert!(error.to_string().contains("after 1 iterations"));
+        assert!(shake(
+            &constraints,
+            &before,
+            &mut pos,
+            &mut vel,
+            &[1.; 16],
+            0.005,
+            1e-6,
+            100,
+            &region
+        )
+        .is_ok());
+    }
+}
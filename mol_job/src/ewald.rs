@@ -0,0 +1,506 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    cell_list::{for_each_pair, CellList},
+    potential::{AtomicReal, PotentialEnergy},
+    state::MolecularState,
+};
+use d_vector::{reset_array, DVector, Real};
+use std::sync::{atomic::Ordering, Mutex, RwLock, RwLockReadGuard};
+
+#[cfg(feature = "f64")]
+pub(crate) use libm::erfc;
+#[cfg(not(feature = "f64"))]
+pub(crate) use libm::erfcf as erfc;
+
+const PI: Real = std::f64::consts::PI as Real;
+
+/// Coulomb interactions of point charges in a periodic box by Ewald summation,
+/// in units where the Coulomb constant is 1. Charges are read from the state
+/// by `attach`; masses are left to the integrators, as for every potential.
+///
+/// Pairs closer than `r_cut` interact through the screened `erfc(alpha r) / r`,
+/// the rest is summed over wave vectors `2π n / L` with `|n| <= k_max`.
+/// A larger `alpha` moves work from the real-space sum to the reciprocal one.
+/// A net charge is neutralised by a uniform background.
+#[derive(Debug)]
+pub struct Ewald {
+    alpha: Real,
+    r_cut: Real,
+    k_max: i32,
+    charges: RwLock<Vec<Real>>,
+    u_sum: AtomicReal,
+    v_sum: AtomicReal,
+    w_sum: Mutex<[[Real; 3]; 3]>,
+}
+
+impl Ewald {
+    pub fn new(alpha: Real, r_cut: Real, k_max: i32) -> Self {
+        Self {
+            alpha,
+            r_cut,
+            k_max,
+            charges: RwLock::default(),
+            u_sum: AtomicReal::new(0.0),
+            v_sum: AtomicReal::new(0.0),
+            w_sum: Mutex::default(),
+        }
+    }
+
+    pub fn alpha(&self) -> Real {
+        self.alpha
+    }
+
+    pub fn r_cut(&self) -> Real {
+        self.r_cut
+    }
+
+    pub fn k_max(&self) -> i32 {
+        self.k_max
+    }
+
+    pub(crate) fn dimensions(&self, boundaries: &dyn BoundaryConditions<3>) -> [Real; 3] {
+        let dimensions = *boundaries
+            .box_dimensions()
+            .expect("Ewald summation needs a periodic box");
+        assert!(
+            dimensions.iter().all(|length| 2. * self.r_cut <= *length),
+            "Real-space cutoff must not exceed half the box"
+        );
+        dimensions
+    }
+
+    /// Wave vectors of half of the reciprocal space, `k` and `-k` being the same
+    /// up to a complex conjugate, with twice the weight `exp(-k² / 4 alpha²) / k²`.
+    fn wave_vectors(&self, dimensions: &[Real; 3]) -> Vec<(DVector<3>, Real)> {
+        let mut vectors = Vec::new();
+        let k_max = self.k_max;
+        for nx in 0..=k_max {
+            for ny in -k_max..=k_max {
+                for nz in -k_max..=k_max {
+                    let n = [nx, ny, nz];
+                    let first = n.iter().find(|n| **n != 0);
+                    if first.is_none_or(|n| *n < 0)
+                        || n.iter().map(|n| n * n).sum::<i32>() > k_max * k_max
+                    {
+                        continue;
+                    }
+                    let mut k = [0 as Real; 3];
+                    for ((k, n), length) in k.iter_mut().zip(n).zip(dimensions) {
+                        *k = 2. * PI * n as Real / length;
+                    }
+                    let k = DVector::from(k);
+                    let kk = k.square_length();
+                    let weight = 2. * (-kk / (4. * self.alpha * self.alpha)).exp() / kk;
+                    vectors.push((k, weight));
+                }
+            }
+        }
+        vectors
+    }
+
+    /// Force value and energy of the screened real-space interaction of a pair.
+    fn real_space(&self, qq: Real, rr: Real) -> Option<(Real, Real)> {
+        if rr >= self.r_cut * self.r_cut {
+            return None;
+        }
+        let r = rr.sqrt();
+        let screened = erfc(self.alpha * r) / r;
+        let gaussian = 2. * self.alpha / PI.sqrt() * (-self.alpha * self.alpha * rr).exp();
+        Some((qq * (screened + gaussian) / rr, qq * screened))
+    }
+
+    /// Energy of a uniform background cancelling the net charge `q_sum`.
+    fn background(&self, q_sum: Real, volume: Real) -> Real {
+        -PI * q_sum * q_sum / (2. * volume * self.alpha * self.alpha)
+    }
+
+    /// Adds the real-space forces to `acc` and their virial to `w_sum`;
+    /// returns the real-space energy. Pairs come from a cell list whenever
+    /// the box holds one of cells `r_cut` wide.
+    pub(crate) fn real_space_sum(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+        w_sum: &mut [[Real; 3]; 3],
+    ) -> Real {
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let mut u_real = 0 as Real;
+        let interact = |j1: usize, j2: usize| {
+            let qq = q(j1) * q(j2);
+            if qq == 0. {
+                return;
+            }
+            let mut dr = &pos[j1] - &pos[j2];
+            boundaries.minimum_image(&mut dr);
+            if let Some((force_value, u)) = self.real_space(qq, dr.square_length()) {
+                add_dyad(w_sum, force_value, &dr);
+                let force = force_value * dr;
+                acc[j1] += &force;
+                acc[j2] -= &force;
+                u_real += u;
+            }
+        };
+        match boundaries
+            .box_dimensions()
+            .and_then(|dimensions| CellList::new(pos, dimensions, self.r_cut))
+        {
+            Some(cells) => cells.for_each_pair(interact),
+            None => for_each_pair(pos.len(), interact),
+        }
+        u_real
+    }
+
+    /// Real-space energy of the charge of `atom` at `position` with the
+    /// other atoms of `pos`.
+    pub(crate) fn real_space_atom(
+        &self,
+        atom: usize,
+        position: &DVector<3>,
+        pos: &[DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) -> Real {
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let q_atom = q(atom);
+        let mut u_real = 0 as Real;
+        for j in (0..pos.len()).filter(|j| *j != atom) {
+            let mut dr = position - &pos[j];
+            boundaries.minimum_image(&mut dr);
+            if let Some((_, u)) = self.real_space(q_atom * q(j), dr.square_length()) {
+                u_real += u;
+            }
+        }
+        u_real
+    }
+
+    /// Self energy of the charge of `atom` and what it adds to the energy of
+    /// the background of the other atoms of `pos`.
+    pub(crate) fn self_and_background_atom(
+        &self,
+        atom: usize,
+        pos: &[DVector<3>],
+        volume: Real,
+    ) -> Real {
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let q_atom = q(atom);
+        let q_others: Real = (0..pos.len()).filter(|j| *j != atom).map(q).sum();
+        let u_self = -self.alpha / PI.sqrt() * q_atom * q_atom;
+        u_self + self.background(q_others + q_atom, volume) - self.background(q_others, volume)
+    }
+
+    /// Energy of every charge with its own screening cloud and of the background,
+    /// which depend on the positions of none of `n_mol` atoms.
+    pub(crate) fn self_and_background(
+        &self,
+        n_mol: usize,
+        volume: Real,
+        w_sum: &mut [[Real; 3]; 3],
+    ) -> Real {
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let (q_sum, qq_sum) = (0..n_mol).fold((0., 0.), |(q_sum, qq_sum), j| {
+            (q_sum + q(j), qq_sum + q(j) * q(j))
+        });
+        let u_self = -self.alpha / PI.sqrt() * qq_sum;
+        // The background energy goes as 1 / V and so contributes 3 times itself.
+        let u_background = self.background(q_sum, volume);
+        for (a, row) in w_sum.iter_mut().enumerate() {
+            row[a] += u_background;
+        }
+        u_self + u_background
+    }
+
+    pub(crate) fn charges(&self) -> RwLockReadGuard<'_, Vec<Real>> {
+        self.charges.read().unwrap()
+    }
+
+    pub(crate) fn store(&self, u_sum: Real, w_sum: [[Real; 3]; 3]) {
+        let v_sum = (0..3).map(|a| w_sum[a][a]).sum();
+        self.u_sum.store(u_sum, Ordering::SeqCst);
+        self.v_sum.store(v_sum, Ordering::SeqCst);
+        *self.w_sum.lock().unwrap() = w_sum;
+    }
+}
+
+impl PotentialEnergy<3> for Ewald {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        assert_eq!(pos.len(), acc.len());
+        let dimensions = self.dimensions(boundaries);
+        let volume = boundaries.volume();
+        let mut w_sum = [[0 as Real; 3]; 3];
+        let u_real = self.real_space_sum(pos, acc, boundaries, &mut w_sum);
+        let u_fixed = self.self_and_background(pos.len(), volume, &mut w_sum);
+
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let mut u_reciprocal = 0 as Real;
+        let mut phases = vec![(0 as Real, 0 as Real); pos.len()];
+        for (k, weight) in self.wave_vectors(&dimensions) {
+            let (mut c, mut s) = (0 as Real, 0 as Real);
+            for (j, (phase, position)) in phases.iter_mut().zip(pos).enumerate() {
+                let kr = &k * position;
+                *phase = (kr.cos(), kr.sin());
+                c += q(j) * phase.0;
+                s += q(j) * phase.1;
+            }
+            let u = 2. * PI / volume * weight * (c * c + s * s);
+            u_reciprocal += u;
+            for (j, (acceleration, (cos, sin))) in acc.iter_mut().zip(&phases).enumerate() {
+                let force_value = 4. * PI / volume * weight * q(j) * (c * sin - s * cos);
+                *acceleration += force_value * &k;
+            }
+            let kk = k.square_length();
+            let stretch = 1. / kk + 1. / (4. * self.alpha * self.alpha);
+            for (a, row) in w_sum.iter_mut().enumerate() {
+                row[a] += u;
+            }
+            add_dyad(&mut w_sum, -2. * stretch * u, &k);
+        }
+        self.store(u_real + u_reciprocal + u_fixed, w_sum);
+    }
+
+    fn attach(&self, state: &dyn MolecularState<3>) {
+        self.charges
+            .write()
+            .unwrap()
+            .clone_from(&state.get_charges());
+    }
+
+    fn u_sum(&self) -> Real {
+        self.u_sum.load(Ordering::SeqCst)
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.v_sum.load(Ordering::SeqCst)
+    }
+
+    fn virial_tensor(&self) -> [[Real; 3]; 3] {
+        *self.w_sum.lock().unwrap()
+    }
+
+    /// Interaction of the charge of `atom` with all the others and with its own
+    /// periodic images, the reciprocal part of it costing a pass over every wave vector.
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<3>,
+        pos: &[DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) -> Real {
+        let dimensions = self.dimensions(boundaries);
+        let volume = boundaries.volume();
+        let charges = self.charges.read().unwrap();
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let q_atom = q(atom);
+        if q_atom == 0. {
+            return 0.;
+        }
+        let others = || (0..pos.len()).filter(|j| *j != atom);
+
+        let mut u_reciprocal = 0 as Real;
+        for (k, weight) in self.wave_vectors(&dimensions) {
+            let (mut c, mut s) = (0 as Real, 0 as Real);
+            for j in others() {
+                let kr = &k * &pos[j];
+                c += q(j) * kr.cos();
+                s += q(j) * kr.sin();
+            }
+            let kr = &k * position;
+            let cross = q_atom * (c * kr.cos() + s * kr.sin());
+            u_reciprocal += 2. * PI / volume * weight * (2. * cross + q_atom * q_atom);
+        }
+
+        drop(charges);
+        self.real_space_atom(atom, position, pos, boundaries)
+            + u_reciprocal
+            + self.self_and_background_atom(atom, pos, volume)
+    }
+
+    fn min_box_length(&self) -> Real {
+        2. * self.r_cut
+    }
+}
+
+pub(crate) fn add_dyad(tensor: &mut [[Real; 3]; 3], factor: Real, v: &DVector<3>) {
+    let x = v.components();
+    for (row, a) in tensor.iter_mut().zip(x) {
+        for (w, b) in row.iter_mut().zip(x) {
+            *w += factor * (a * b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, state::State};
+
+    /// Ions on the sites of a simple cubic lattice of spacing `a`,
+    /// `n` cells along each axis, every cell holding the given basis.
+    fn crystal(n: usize, a: Real, basis: &[([Real; 3], Real)]) -> (Region<3>, State<3>) {
+        let length = n as Real * a;
+        let state = State::default();
+        for (i, j, k) in
+            (0..n).flat_map(|i| (0..n).flat_map(move |j| (0..n).map(move |k| (i, j, k))))
+        {
+            for (offset, charge) in basis {
+                let mut x = [0 as Real; 3];
+                for ((x, c), o) in x.iter_mut().zip([i, j, k]).zip(offset) {
+                    *x = a * (c as Real + o) - length / 2.;
+                }
+                state.insert_atom(DVector::from(x));
+                *state.get_charges().last_mut().unwrap() = *charge;
+            }
+        }
+        (Region::new([length; 3]), state)
+    }
+
+    fn rock_salt() -> (Region<3>, State<3>) {
+        let (region, state) = crystal(4, 1., &[([0.; 3], 1.)]);
+        for (position, charge) in state.get_pos().iter().zip(state.get_charges().iter_mut()) {
+            let parity: Real = position.components().iter().map(|x| x + 2.).sum();
+            *charge = if parity.round() as i32 % 2 == 0 {
+                1.
+            } else {
+                -1.
+            };
+        }
+        (region, state)
+    }
+
+    /// Energy per ion of a binary crystal with nearest neighbours at unit distance.
+    fn energy_per_ion(region: &Region<3>, state: &State<3>) -> (Real, Ewald) {
+        let ewald = Ewald::new(2., region.dimensions()[0] / 2., 12);
+        ewald.attach(state);
+        let mut acc = vec![DVector::default(); state.get_pos().len()];
+        ewald.compute_forces(&state.get_pos(), &mut acc, region);
+        assert!(acc.iter().all(|a| a.length() < 1e-3));
+        (ewald.u_sum() / acc.len() as Real, ewald)
+    }
+
+    #[test]
+    fn sodium_chloride_madelung_constant() {
+        let (region, state) = rock_salt();
+        let (u, ewald) = energy_per_ion(&region, &state);
+        assert!((u + 1.747565 / 2.).abs() < 1e-4, "u = {}", u);
+        // Coulomb energy scales as the inverse length, so the virial equals it.
+        let w = ewald.virial_tensor();
+        let u_sum = ewald.u_sum();
+        assert!((ewald.virial_sum() - u_sum).abs() < 1e-3 * u_sum.abs());
+        assert!((w[0][0] - w[1][1]).abs() < 1e-3 * u_sum.abs() && w[0][1].abs() < 1e-3);
+    }
+
+    #[test]
+    fn caesium_chloride_madelung_constant() {
+        let a = 2. / (3 as Real).sqrt();
+        let (region, state) = crystal(3, a, &[([0.; 3], 1.), ([0.5; 3], -1.)]);
+        let (u, _) = energy_per_ion(&region, &state);
+        assert!((u + 1.762675 / 2.).abs() < 1e-4, "u = {}", u);
+    }
+
+    #[test]
+    fn real_space_cell_list_matches_all_pairs() {
+        let (region, state) = crystal(6, 1., &[([0.; 3], 1.)]);
+        for (i, (position, charge)) in state
+            .get_pos()
+            .iter_mut()
+            .zip(state.get_charges().iter_mut())
+            .enumerate()
+        {
+            let x = i as Real;
+            *position += &DVector::from([0.2 * (0.7 * x).sin(), 0.2 * (1.3 * x).cos(), 0.]);
+            *charge = if i % 2 == 0 { 1. } else { -1. };
+        }
+        let ewald = Ewald::new(2., 1.9, 0);
+        ewald.attach(&state);
+        let pos = state.get_pos();
+        assert!(CellList::new(&pos, region.dimensions(), 1.9).is_some());
+        let mut acc = vec![DVector::default(); pos.len()];
+        let u = ewald.real_space_sum(&pos, &mut acc, &region, &mut [[0.; 3]; 3]);
+
+        let charges = state.get_charges();
+        let mut expected = 0 as Real;
+        for_each_pair(pos.len(), |j1, j2| {
+            let mut dr = &pos[j1] - &pos[j2];
+            region.minimum_image(&mut dr);
+            if let Some((_, u)) = ewald.real_space(charges[j1] * charges[j2], dr.square_length()) {
+                expected += u;
+            }
+        });
+        assert!(
+            (u - expected).abs() < 1e-4 * expected.abs(),
+            "{} vs {}",
+            u,
+            expected
+        );
+    }
+
+    #[test]
+    fn atom_energy_matches_total_difference() {
+        let (region, state) = rock_salt();
+        state.get_charges()[5] = 0.5;
+        let ewald = Ewald::new(1.5, 2., 8);
+        ewald.attach(&state);
+        let mut pos = state.get_pos().clone();
+        let mut acc = vec![DVector::default(); pos.len()];
+        let moved = &pos[5] + &DVector::from([0.3, -0.2, 0.1]);
+        let du = ewald.atom_energy(5, &moved, &pos, &region)
+            - ewald.atom_energy(5, &pos[5], &pos, &region);
+
+        ewald.compute_forces(&pos, &mut acc, &region);
+        let before = ewald.u_sum();
+        pos[5] = moved;
+        state.get_pos()[5] = pos[5].clone();
+        ewald.compute_forces(&pos, &mut acc, &region);
+        let after = ewald.u_sum();
+        assert!(
+            (after - before - du).abs() < 1e-4,
+            "{} vs {}",
+            after - before,
+            du
+        );
+
+        let h = 1e-2;
+        let nudged = |dy: Real| &pos[5] + &DVector::from([0., dy, 0.]);
+        let slope = (ewald.atom_energy(5, &nudged(h), &pos, &region)
+            - ewald.atom_energy(5, &nudged(-h), &pos, &region))
+            / (2. * h);
+        let force = acc[5].components()[1];
+        assert!(force.abs() > 0.1);
+        assert!(
+            (force + slope).abs() < 1e-2 * force.abs(),
+            "{} vs {}",
+            force,
+            -slope
+        );
+
+        // Taking the atom out altogether leaves the rest with a net charge.
+        let removed = ewald.atom_energy(5, &pos[5], &pos, &region);
+        state.remove_atom(5);
+        ewald.attach(&state);
+        let rest = state.get_pos().clone();
+        let mut acc = vec![DVector::default(); rest.len()];
+        ewald.compute_forces(&rest, &mut acc, &region);
+        assert!((after - ewald.u_sum() - removed).abs() < 1e-4);
+    }
+}
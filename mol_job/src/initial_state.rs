@@ -9,7 +9,10 @@ pub fn cubic_lattice<const D: usize>(n_mol: usize, density: Real) -> (Region<D>,
     let size = vol.powf(1. / D as Real);
     let region = Region::new([size; D]);
 
-    let dim = (n_mol as Real).powf(1. / D as Real) as usize;
+    let mut dim = (n_mol as Real).powf(1. / D as Real).round() as usize;
+    while dim.pow(D as u32) > n_mol {
+        dim -= 1;
+    }
     let cells = [dim; D];
     let mut pos = Vec::with_capacity(number_of_atoms(&cells));
     lattice(&cells, &calc_gap(&region, &cells), &mut pos, [0.; D], 0);
@@ -42,7 +45,7 @@ fn lattice<const D: usize>(
     current_index: usize,
 ) {
     for i in 0..cells[current_index] {
-        current[current_index] = (0.5_f32 + i as Real) * gap[current_index];
+        current[current_index] = (0.5 + i as Real) * gap[current_index];
         if current_index == D - 1 {
             pos.push(DVector::from(current));
         } else {
@@ -0,0 +1,269 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions, constraints::ConstraintError, potential::PotentialEnergy,
+    state::MolecularState, verlet,
+};
+use d_vector::Real;
+use std::fmt::{self, Debug};
+
+/// Why an integrator could not take a step; `Job::run` stops at the first one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepError {
+    /// The constraints of the topology could not be kept.
+    Constraint(ConstraintError),
+    /// A barostat shrank the box below what the potential works in.
+    BoxTooSmall { length: Real, min_length: Real },
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constraint(error) => write!(f, "{}", error),
+            Self::BoxTooSmall { length, min_length } => write!(
+                f,
+                "box length {} fell below the {} the potential needs",
+                length, min_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StepError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Constraint(error) => Some(error),
+            Self::BoxTooSmall { .. } => None,
+        }
+    }
+}
+
+impl From<ConstraintError> for StepError {
+    fn from(error: ConstraintError) -> Self {
+        Self::Constraint(error)
+    }
+}
+
+/// Advances the state by one time step; `Job::run` calls it once per step.
+pub trait Integrator<const D: usize>: Debug {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError>;
+    /// Computes the accelerations at the current positions for the first kick;
+    /// `Job::run` calls it before every run, so a state that is set up or
+    /// changed between runs starts from its own forces.
+    fn prime(
+        &self,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) {
+        let masses = state.atom_masses();
+        let mut pos = state.get_pos();
+        verlet::apply_boundary_conditions(
+            boundaries,
+            &mut pos,
+            &mut state.get_vel(),
+            &mut state.get_images(),
+        );
+        verlet::compute_accelerations(
+            potential_energy,
+            &pos,
+            &mut state.get_acc(),
+            &masses,
+            boundaries,
+        );
+    }
+    /// Virial tensor of the constraint forces of the last step, which `Job`
+    /// adds to that of the potential; zero for unconstrained dynamics.
+    fn constraint_virial(&self) -> [[Real; D]; D] {
+        [[0.0; D]; D]
+    }
+}
+
+/// Plain NVE dynamics of `verlet::single_step`, the velocity Verlet scheme
+/// once `prime` has computed the accelerations of the first kick.
+#[derive(Debug, Default)]
+pub struct Leapfrog;
+
+impl<const D: usize> Integrator<D> for Leapfrog {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let masses = state.atom_masses();
+        verlet::single_step(
+            delta_t,
+            &mut state.get_pos(),
+            &mut state.get_vel(),
+            &mut state.get_acc(),
+            &mut state.get_images(),
+            &masses,
+            boundaries,
+            potential_energy,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, lennard_jones::LennardJones, state::State};
+    use d_vector::DVector;
+
+    fn dimer() -> State<3> {
+        let state = State::default();
+        *state.get_pos() = vec![DVector::from([0.5, 0., 0.]), DVector::from([-0.5, 0., 0.])];
+        *state.get_vel() = vec![DVector::default(); 2];
+        *state.get_acc() = vec![DVector::default(); 2];
+        *state.get_images() = vec![[0; 3]; 2];
+        state
+    }
+
+    fn energy(state: &State<3>, potential: &LennardJones) -> Real {
+        let kinetic: Real = state
+            .get_vel()
+            .iter()
+            .map(|v| 0.5 * v.square_length())
+            .sum();
+        kinetic + PotentialEnergy::<3>::u_sum(potential)
+    }
+
+    #[test]
+    fn primed_leapfrog_conserves_energy_from_the_start() {
+        let region = Region::new([10.; 3]);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let state = dimer();
+        Leapfrog.prime(&state, &region, &wca);
+        let initial = energy(&state, &wca);
+
+        for _ in 0..500 {
+            Leapfrog.single_step(1e-3, &state, &region, &wca).unwrap();
+            assert!((energy(&state, &wca) - initial).abs() < 1e-3 * initial);
+        }
+    }
+
+    #[test]
+    fn priming_again_restarts_a_changed_state() {
+        let region = Region::new([10.; 3]);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let state = dimer();
+        Leapfrog.prime(&state, &region, &wca);
+        for _ in 0..100 {
+            Leapfrog.single_step(1e-3, &state, &region, &wca).unwrap();
+        }
+
+        // Squeezed and at rest again: the forces of the old positions are stale.
+        *state.get_pos() = vec![
+            DVector::from([0.45, 0., 0.]),
+            DVector::from([-0.45, 0., 0.]),
+        ];
+        *state.get_vel() = vec![DVector::default(); 2];
+        Leapfrog.prime(&state, &region, &wca);
+        let initial = energy(&state, &wca);
+        for _ in 0..500 {
+            Leapfrog.single_step(1e-3, &state, &region, &wca).unwrap();
+            assert!((energy(&state, &wca) - initial).abs() < 1e-3 * initial);
+        }
+    }
+
+    #[test]
+    fn force_shifted_fluid_conserves_energy() {
+        use crate::{
+            initial_state::{cubic_lattice, randomize_vectors},
+            lennard_jones::Truncation,
+        };
+
+        let (region, pos) = cubic_lattice::<3>(125, 0.8);
+        let state = State::default();
+        *state.get_vel() = vec![DVector::default(); pos.len()];
+        *state.get_acc() = vec![DVector::default(); pos.len()];
+        *state.get_images() = vec![[0; 3]; pos.len()];
+        *state.get_pos() = pos;
+        randomize_vectors(&mut state.get_vel(), 1.5);
+        let potential = LennardJones::default()
+            .truncation(Truncation::ForceShifted)
+            .cell_list();
+
+        let integrator = Leapfrog;
+        integrator.prime(&state, &region, &potential);
+        let initial = energy(&state, &potential);
+        for _ in 0..1000 {
+            integrator
+                .single_step(2e-3, &state, &region, &potential)
+                .unwrap();
+        }
+        let drift = (energy(&state, &potential) - initial) / state.get_pos().len() as Real;
+        assert!(drift.abs() < 2e-3, "drift = {}", drift);
+    }
+
+    /// Mean `m v² / D` of the atoms of each of two species.
+    fn species_temperatures(state: &State<3>) -> [Real; 2] {
+        let masses = state.atom_masses();
+        let species = state.get_species();
+        let mut sums = [0 as Real; 2];
+        let mut counts = [0; 2];
+        for ((v, m), s) in state.get_vel().iter().zip(masses).zip(species.iter()) {
+            sums[*s] += m * v.square_length() / 3.;
+            counts[*s] += 1;
+        }
+        [sums[0] / counts[0] as Real, sums[1] / counts[1] as Real]
+    }
+
+    #[test]
+    fn unequal_masses_reach_equipartition() {
+        use crate::initial_state::{cubic_lattice, randomize_vectors};
+
+        let (region, pos) = cubic_lattice::<3>(125, 0.7);
+        let state = State::default();
+        for position in pos {
+            state.insert_atom(position);
+        }
+        for (j, s) in state.get_species().iter_mut().enumerate() {
+            *s = j % 2;
+        }
+        *state.get_masses() = vec![1., 4.];
+        // Same speed for all, so the heavy atoms start four times as hot.
+        randomize_vectors(&mut state.get_vel(), 1.);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let integrator = Leapfrog;
+        integrator.prime(&state, &region, &wca);
+        let steps = 8000;
+        let mut sums = [0 as Real; 2];
+        for step in 0..steps {
+            integrator.single_step(4e-3, &state, &region, &wca).unwrap();
+            if step >= 1000 {
+                let t = species_temperatures(&state);
+                sums[0] += t[0];
+                sums[1] += t[1];
+            }
+        }
+        let [light, heavy] = sums.map(|sum| sum / (steps - 1000) as Real);
+        // Each species has only a few dozen atoms, so average over a long run.
+        assert!((heavy / light - 1.).abs() < 0.1, "{} vs {}", light, heavy);
+    }
+
+    #[test]
+    fn leapfrog_keeps_stale_accelerations_until_primed() {
+        let region = Region::new([10.; 3]);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let stale = dimer();
+        let primed = dimer();
+
+        Leapfrog.single_step(1e-3, &stale, &region, &wca).unwrap();
+        Leapfrog.prime(&primed, &region, &wca);
+        Leapfrog.single_step(1e-3, &primed, &region, &wca).unwrap();
+        let v_stale = stale.get_vel()[0].components()[0];
+        let v_primed = primed.get_vel()[0].components()[0];
+        assert!(v_stale > 0.);
+        assert!((v_primed / v_stale - 2.).abs() < 1e-2);
+    }
+}
@@ -1,11 +1,15 @@
 #![allow(unused, dead_code)]
 
 use crate::{
+    barostat::Barostat,
     boundaries::{BoundaryConditions, Region},
+    integrator::{Integrator, Leapfrog, StepError},
     lennard_jones::LennardJones,
+    minimize::{Minimization, Minimizer},
     potential::PotentialEnergy,
     prop::{Props, TrivialProps},
     state::{MolecularState, State},
+    topology::Topology,
     verlet,
 };
 use d_vector::{DVector, Real};
@@ -20,6 +24,9 @@ pub struct Job<const D: usize> {
     boundaries: Box<dyn BoundaryConditions<D>>,
     potential: Box<dyn PotentialEnergy<D>>,
     props: Box<dyn Props<D>>,
+    integrator: Box<dyn Integrator<D>>,
+    barostat: Option<Box<dyn Barostat<D>>>,
+    minimization: Option<Minimization>,
     step_count: usize,
     delta_t: Real,
     more_cycles: bool,
@@ -31,7 +38,10 @@ impl<const D: usize> Default for Job<D> {
             state: Box::new(State::default()),
             boundaries: Box::new(Region::new([50.; D])),
             potential: Box::new(LennardJones::default()),
-            props: Box::new(TrivialProps::default()),
+            props: Box::new(TrivialProps),
+            integrator: Box::new(Leapfrog),
+            barostat: None,
+            minimization: None,
             step_count: 0,
             delta_t: 0.005,
             more_cycles: true,
@@ -40,19 +50,30 @@ impl<const D: usize> Default for Job<D> {
 }
 
 impl<const D: usize> Job<D> {
-    pub fn run(&mut self, steps: usize) -> usize {
+    /// Advances `steps` steps, starting from the forces at the current
+    /// positions, and returns how many it overshot by; stops at the first
+    /// step the integrator fails.
+    pub fn run(&mut self, steps: usize) -> Result<usize, StepError> {
         self.more_cycles = true;
         let step_limit = self.step_count() + steps;
+        self.potential.attach(self.state.as_ref());
+        self.integrator.prime(
+            self.state.as_ref(),
+            self.boundaries.as_ref(),
+            self.potential.as_ref(),
+        );
         while self.more_cycles {
             self.advance_step_count();
-            verlet::single_step(
+            let time_now = self.time_now();
+            self.boundaries.sync(time_now);
+            self.couple_pressure()?;
+            self.integrator.single_step(
                 self.delta_t(),
-                &mut self.state.get_pos(),
-                &mut self.state.get_vel(),
-                &mut self.state.get_acc(),
+                self.state.as_ref(),
                 self.boundaries.as_ref(),
                 self.potential.as_ref(),
-            );
+            )?;
+            self.couple_pressure()?;
             self.update_props();
             self.state.sync(self.time_now());
 
@@ -60,13 +81,35 @@ impl<const D: usize> Job<D> {
                 self.more_cycles = false;
             }
         }
-        self.step_count() - step_limit
+        Ok(self.step_count() - step_limit)
     }
 
     fn advance_step_count(&mut self) {
         self.step_count += 1;
     }
 
+    fn couple_pressure(&mut self) -> Result<(), StepError> {
+        if let Some(barostat) = self.barostat.as_ref() {
+            let potential = Constrained {
+                potential: self.potential.as_ref(),
+                virial: self.integrator.constraint_virial(),
+            };
+            barostat.couple(
+                self.delta_t / 2.,
+                self.state.as_ref(),
+                self.boundaries.as_mut(),
+                &potential,
+            );
+            let min_length = self.potential.min_box_length();
+            if let Some(dimensions) = self.boundaries.box_dimensions() {
+                if let Some(length) = dimensions.iter().copied().find(|l| *l < min_length) {
+                    return Err(StepError::BoxTooSmall { length, min_length });
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn delta_t(&self) -> Real {
         self.delta_t
     }
@@ -79,9 +122,12 @@ impl<const D: usize> Job<D> {
             self.potential.virial_sum()
         );*/
         self.props.eval_props(
-            self.potential.as_ref(),
-            &self.state.get_pos(),
-            &self.state.get_vel(),
+            &Constrained {
+                potential: self.potential.as_ref(),
+                virial: self.integrator.constraint_virial(),
+            },
+            self.boundaries.as_ref(),
+            self.state.as_ref(),
         );
         self.props.accum_props();
         if self.props.need_avg(self.step_count()) {
@@ -99,6 +145,48 @@ impl<const D: usize> Job<D> {
         self.step_count
     }
 
+    pub fn volume(&self) -> Real {
+        self.boundaries.volume()
+    }
+
+    /// Instantaneous pressure seen by the barostat, if there is one.
+    pub fn pressure(&self) -> Option<Real> {
+        self.barostat.as_ref().map(|barostat| barostat.pressure())
+    }
+
+    /// Result of the energy minimisation done in `JobSetup::minimize`, if any.
+    pub fn minimization(&self) -> Option<Minimization> {
+        self.minimization
+    }
+
+    pub fn neighbour_list_rebuilds(&self) -> Option<usize> {
+        self.potential.neighbour_list_rebuilds()
+    }
+
+    pub fn potential(&self) -> &dyn PotentialEnergy<D> {
+        self.potential.as_ref()
+    }
+
+    /// Energy of every term of the potential after the last step, in the
+    /// order they were added to a `Composite`.
+    pub fn u_terms(&self) -> Vec<Real> {
+        self.potential.u_terms()
+    }
+
+    pub fn virial_terms(&self) -> Vec<Real> {
+        self.potential.virial_terms()
+    }
+
+    /// Positions with the boxes crossed since the start added back, for diffusion.
+    pub fn unwrapped_pos(&self) -> Vec<DVector<D>> {
+        let pos = self.state.get_pos();
+        let images = self.state.get_images();
+        pos.iter()
+            .zip(images.iter())
+            .map(|(position, image)| self.boundaries.unwrap(position, image))
+            .collect()
+    }
+
     pub fn vel_sum(&self) -> DVector<D> {
         let mut result = DVector::default();
         for velocity in self.state.get_vel().iter() {
@@ -136,6 +224,16 @@ impl<const D: usize> JobSetup<D> {
         self
     }
 
+    pub fn integrator(mut self, integrator: impl Integrator<D> + 'static) -> Self {
+        self.0.integrator = Box::new(integrator);
+        self
+    }
+
+    pub fn barostat(mut self, barostat: impl Barostat<D> + 'static) -> Self {
+        self.0.barostat = Some(Box::new(barostat));
+        self
+    }
+
     pub fn boundaries(mut self, boundaries: impl BoundaryConditions<D> + 'static) -> Self {
         self.0.boundaries = Box::new(boundaries);
         self
@@ -146,16 +244,79 @@ impl<const D: usize> JobSetup<D> {
         *self.0.state.get_pos() = pos;
         *self.0.state.get_vel() = vec![DVector::default(); n_mol];
         *self.0.state.get_acc() = vec![DVector::default(); n_mol];
+        *self.0.state.get_images() = vec![[0; D]; n_mol];
+        *self.0.state.get_species() = vec![0; n_mol];
+        *self.0.state.get_charges() = vec![0.; n_mol];
+        *self.0.state.get_topology() = Topology::default();
         self
     }
 
+    /// Species of every atom set by `init_pos`, which are all 0 otherwise.
+    pub fn species(mut self, species: Vec<usize>) -> Self {
+        assert_eq!(self.0.state.get_pos().len(), species.len());
+        *self.0.state.get_species() = species;
+        self
+    }
+
+    /// Mass of every species, 1 for those left out. Call it before `random_vel`.
+    pub fn masses(mut self, masses: Vec<Real>) -> Self {
+        *self.0.state.get_masses() = masses;
+        self
+    }
+
+    /// Charges of every atom set by `init_pos`, which are all 0 otherwise.
+    pub fn charges(mut self, charges: Vec<Real>) -> Self {
+        assert_eq!(self.0.state.get_pos().len(), charges.len());
+        *self.0.state.get_charges() = charges;
+        self
+    }
+
+    /// Bonds, angles and dihedrals between the atoms set by `init_pos`.
+    pub fn topology(mut self, topology: Topology) -> Self {
+        assert!(
+            topology.n_atoms() <= self.0.state.get_pos().len(),
+            "Topology refers to atoms that do not exist"
+        );
+        *self.0.state.get_topology() = topology;
+        self
+    }
+
+    /// Random velocities whose size goes as the square root of `temperature`
+    /// over the mass of the atom, with the total momentum taken out.
     pub fn random_vel(mut self, temperature: Real) -> Self {
-        let n_mol = self.0.state.get_pos().len();
+        let masses = self.0.state.atom_masses();
+        let n_mol = masses.len();
         let vel_mag = (temperature * (D as Real) * (1. - 1. / (n_mol as Real))).sqrt();
-        crate::initial_state::randomize_vectors(&mut self.0.state.get_vel(), vel_mag);
-        let sum = self.0.vel_sum();
-        let k = -1. / n_mol as Real;
-        crate::initial_state::shift_vectors(&mut self.0.state.get_vel(), &(k * sum));
+        let mut vel = self.0.state.get_vel();
+        crate::initial_state::randomize_vectors(&mut vel, vel_mag);
+        let mut momentum = DVector::default();
+        for (velocity, mass) in vel.iter_mut().zip(masses.iter()) {
+            *velocity = (1. / mass.sqrt()) * &*velocity;
+            momentum += &(*mass * &*velocity);
+        }
+        let k = -1. / masses.iter().sum::<Real>();
+        crate::initial_state::shift_vectors(&mut vel, &(k * momentum));
+        drop(vel);
+        self
+    }
+
+    /// Relaxes the current positions with the current boundaries and potential,
+    /// so call it after those are set. Leaves the state with matching accelerations.
+    pub fn minimize(mut self, minimizer: impl Minimizer<D>) -> Self {
+        let job = &mut self.0;
+        job.potential.attach(job.state.as_ref());
+        let masses = job.state.atom_masses();
+        let mut pos = job.state.get_pos();
+        let result = minimizer.minimize(&mut pos, job.boundaries.as_ref(), job.potential.as_ref());
+        verlet::compute_accelerations(
+            job.potential.as_ref(),
+            &pos,
+            &mut job.state.get_acc(),
+            &masses,
+            job.boundaries.as_ref(),
+        );
+        drop(pos);
+        job.minimization = Some(result);
         self
     }
 
@@ -163,3 +324,63 @@ impl<const D: usize> JobSetup<D> {
         self.0
     }
 }
+
+/// The potential as props and barostats see it, with the virial of the
+/// constraint forces of the integrator added to its own.
+#[derive(Debug)]
+struct Constrained<'a, const D: usize> {
+    potential: &'a dyn PotentialEnergy<D>,
+    virial: [[Real; D]; D],
+}
+
+impl<const D: usize> PotentialEnergy<D> for Constrained<'_, D> {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        self.potential.compute_forces(pos, acc, boundaries);
+    }
+
+    fn u_sum(&self) -> Real {
+        self.potential.u_sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        let trace: Real = (0..D).map(|a| self.virial[a][a]).sum();
+        self.potential.virial_sum() + trace
+    }
+
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        let mut tensor = self.potential.virial_tensor();
+        for (row, other) in tensor.iter_mut().zip(self.virial.iter()) {
+            for (w, o) in row.iter_mut().zip(other) {
+                *w += o;
+            }
+        }
+        tensor
+    }
+
+    fn tail_correction(&self, n_mol: usize, volume: Real) -> (Real, Real) {
+        self.potential.tail_correction(n_mol, volume)
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        self.potential.atom_energy(atom, position, pos, boundaries)
+    }
+
+    fn neighbour_list_rebuilds(&self) -> Option<usize> {
+        self.potential.neighbour_list_rebuilds()
+    }
+
+    fn min_box_length(&self) -> Real {
+        self.potential.min_box_length()
+    }
+}
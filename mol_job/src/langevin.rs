@@ -0,0 +1,192 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    integrator::{Integrator, StepError},
+    potential::PotentialEnergy,
+    state::MolecularState,
+    verlet,
+};
+use d_vector::{DVector, Real};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use std::{cell::RefCell, ops::AddAssign};
+
+/// Langevin dynamics with the BAOAB splitting: half kick, half drift,
+/// exact Ornstein–Uhlenbeck velocity update, half drift, half kick.
+#[derive(Debug)]
+pub struct Langevin {
+    temperature: Real,
+    friction: Real,
+    rng: RefCell<StdRng>,
+}
+
+impl Langevin {
+    /// Noise is seeded from the operating system, use `seed` for reproducible runs.
+    pub fn new(temperature: Real, friction: Real) -> Self {
+        Self {
+            temperature,
+            friction,
+            rng: RefCell::new(StdRng::from_entropy()),
+        }
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        *self.rng.borrow_mut() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn temperature(&self) -> Real {
+        self.temperature
+    }
+
+    pub fn friction(&self) -> Real {
+        self.friction
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn single_step<const D: usize>(
+        &self,
+        delta_t: Real,
+        pos: &mut [DVector<D>],
+        vel: &mut [DVector<D>],
+        acc: &mut [DVector<D>],
+        images: &mut Vec<[i32; D]>,
+        masses: &[Real],
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) {
+        verlet::leapfrog_end(delta_t, vel, acc);
+        drift(delta_t / 2., pos, vel);
+        self.thermalize(delta_t, vel, masses);
+        drift(delta_t / 2., pos, vel);
+        verlet::apply_boundary_conditions(boundaries, pos, vel, images);
+        verlet::compute_accelerations(potential_energy, pos, acc, masses, boundaries);
+        verlet::leapfrog_end(delta_t, vel, acc);
+    }
+
+    /// Noise of every atom scaled to its mass, so that each one samples
+    /// the Maxwell distribution at `temperature`.
+    fn thermalize<const D: usize>(&self, delta_t: Real, vel: &mut [DVector<D>], masses: &[Real]) {
+        let c1 = (-self.friction * delta_t).exp();
+        let c2 = ((1. - c1 * c1) * self.temperature).sqrt();
+        let mut rng = self.rng.borrow_mut();
+        for (velocity, mass) in vel.iter_mut().zip(masses) {
+            let mut noise = [0 as Real; D];
+            for n in noise.iter_mut() {
+                *n = rng.sample(StandardNormal);
+            }
+            *velocity = c1 * &*velocity + (c2 / mass.sqrt()) * DVector::from(noise);
+        }
+    }
+}
+
+impl<const D: usize> Integrator<D> for Langevin {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let masses = state.atom_masses();
+        Langevin::single_step(
+            self,
+            delta_t,
+            &mut state.get_pos(),
+            &mut state.get_vel(),
+            &mut state.get_acc(),
+            &mut state.get_images(),
+            &masses,
+            boundaries,
+            potential_energy,
+        );
+        Ok(())
+    }
+}
+
+fn drift<const D: usize>(delta_t: Real, pos: &mut [DVector<D>], vel: &[DVector<D>]) {
+    for (position, velocity) in pos.iter_mut().zip(vel.iter()) {
+        position.add_assign(delta_t * velocity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, initial_state::cubic_lattice, lennard_jones::LennardJones};
+
+    fn run(seed: u64, steps: usize) -> (Vec<DVector<3>>, Real) {
+        let (region, mut pos) = cubic_lattice::<3>(64, 0.6);
+        let n_mol = pos.len();
+        let mut vel = vec![DVector::default(); n_mol];
+        let mut acc = vec![DVector::default(); n_mol];
+        let mut images = vec![[0; 3]; n_mol];
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        wca.compute_forces(&pos, &mut acc, &region);
+
+        let langevin = Langevin::new(1.5, 2.).seed(seed);
+        let mut temperature_sum = 0.;
+        for step in 0..steps {
+            langevin.single_step(
+                0.005,
+                &mut pos,
+                &mut vel,
+                &mut acc,
+                &mut images,
+                &vec![1.; n_mol],
+                &region,
+                &wca,
+            );
+            if step >= steps / 2 {
+                let vv_sum: Real = vel.iter().map(|v| v.square_length()).sum();
+                temperature_sum += vv_sum / (3 * n_mol) as Real;
+            }
+        }
+        (pos, temperature_sum / (steps - steps / 2) as Real)
+    }
+
+    #[test]
+    fn reproducible_with_seed() {
+        assert_eq!(run(7, 50), run(7, 50));
+        assert_ne!(run(7, 50).0, run(8, 50).0);
+    }
+
+    #[test]
+    fn samples_target_temperature() {
+        let (_, temperature) = run(42, 2000);
+        assert!((temperature - 1.5).abs() < 0.1, "T = {}", temperature);
+    }
+
+    #[test]
+    fn heavy_atoms_get_the_same_temperature() {
+        use crate::state::State;
+
+        let (region, pos) = cubic_lattice::<3>(64, 0.6);
+        let state = State::default();
+        for position in pos {
+            state.insert_atom(position);
+        }
+        for (j, s) in state.get_species().iter_mut().enumerate() {
+            *s = j % 2;
+        }
+        *state.get_masses() = vec![1., 4.];
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let langevin = Langevin::new(1.5, 2.).seed(3);
+        let steps = 2000;
+        let mut sums = [0 as Real; 2];
+        for step in 0..steps {
+            Integrator::single_step(&langevin, 0.005, &state, &region, &wca).unwrap();
+            if step >= steps / 2 {
+                let vel = state.get_vel();
+                for (j, v) in vel.iter().enumerate() {
+                    sums[j % 2] += [1., 4.][j % 2] * v.square_length();
+                }
+            }
+        }
+        for sum in sums {
+            let temperature = sum / (3 * 32 * (steps / 2)) as Real;
+            assert!((temperature - 1.5).abs() < 0.15, "T = {}", temperature);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    barostat::pressure_tensor,
+    boundaries::{fold, nearest_image, BoundaryConditions, Region},
+    integrator::{Integrator, StepError},
+    potential::PotentialEnergy,
+    prop::{degrees_of_freedom, mvv_sum},
+    state::MolecularState,
+    verlet,
+};
+use d_vector::{DVector, Real};
+use std::{cell::Cell, ops::AddAssign};
+
+/// Periodic box under planar Couette flow along the first axis with the gradient
+/// along the second: the images above the box slide along the first axis at
+/// `shear_rate` times the box height. The offset follows `Job::time_now` via `sync`.
+///
+/// Atoms crossing the second axis have their velocity along the first axis
+/// corrected, so the state holds laboratory velocities, streaming included.
+/// Neighbouring cells are shifted against each other, so cell lists fall back
+/// to all pairs, and unwrapped positions ignore the offset along the first axis.
+/// Neighbour lists count the slide of the offset since they were built.
+#[derive(Debug)]
+pub struct LeesEdwards<const D: usize> {
+    region: Region<D>,
+    shear_rate: Real,
+    offset: Real,
+}
+
+impl<const D: usize> LeesEdwards<D> {
+    pub fn new(dimensions: [Real; D], shear_rate: Real) -> Self {
+        assert!(D >= 2, "Lees–Edwards boundaries need at least two axes");
+        Self {
+            region: Region::new(dimensions),
+            shear_rate,
+            offset: 0.,
+        }
+    }
+
+    pub fn dimensions(&self) -> &[Real; D] {
+        self.region.dimensions()
+    }
+
+    /// Current shift along the first axis of the images above the box.
+    pub fn offset(&self) -> Real {
+        self.offset
+    }
+}
+
+impl<const D: usize> BoundaryConditions<D> for LeesEdwards<D> {
+    fn wrap(&self, pos: &mut DVector<D>) -> [i32; D] {
+        let dimensions = self.dimensions();
+        let mut x = *pos.components();
+        let mut image = [0; D];
+        (x[1], image[1]) = fold(x[1], dimensions[1]);
+        x[0] -= image[1] as Real * self.offset;
+        for (i, (x, n)) in x.iter_mut().zip(image.iter_mut()).enumerate() {
+            if i != 1 {
+                (*x, *n) = fold(*x, dimensions[i]);
+            }
+        }
+        *pos = DVector::from(x);
+        image
+    }
+
+    fn minimum_image(&self, dr: &mut DVector<D>) {
+        let dimensions = self.dimensions();
+        let mut x = *dr.components();
+        let n_y = (x[1] / dimensions[1]).round();
+        x[1] -= n_y * dimensions[1];
+        x[0] -= n_y * self.offset;
+        for (i, x) in x.iter_mut().enumerate() {
+            if i != 1 {
+                *x = nearest_image(*x, dimensions[i]);
+            }
+        }
+        *dr = DVector::from(x);
+    }
+
+    fn confine(&self, position: &mut DVector<D>, velocity: &mut DVector<D>) -> [i32; D] {
+        let image = self.wrap(position);
+        add_along_flow(
+            velocity,
+            -(image[1] as Real) * self.shear_rate * self.dimensions()[1],
+        );
+        image
+    }
+
+    fn unwrap(&self, position: &DVector<D>, image: &[i32; D]) -> DVector<D> {
+        self.region.unwrap(position, image)
+    }
+
+    fn volume(&self) -> Real {
+        self.region.volume()
+    }
+
+    fn at_fractional(&self, fractional: &[Real; D]) -> DVector<D> {
+        self.region.at_fractional(fractional)
+    }
+
+    fn scale(&mut self, factor: Real) {
+        self.region.scale(factor);
+        self.offset *= factor;
+    }
+
+    fn shear_rate(&self) -> Real {
+        self.shear_rate
+    }
+
+    fn shear_offset(&self) -> Real {
+        self.offset
+    }
+
+    fn sync(&mut self, time_now: Real) {
+        let dimensions = self.dimensions();
+        (self.offset, _) = fold(self.shear_rate * dimensions[1] * time_now, dimensions[0]);
+    }
+}
+
+/// SLLOD equations of motion for homogeneous shear at the rate of the boundaries,
+/// integrated with the kick-drift-kick of `Leapfrog` on peculiar velocities.
+///
+/// The state holds laboratory velocities, as `LeesEdwards` expects; peculiar
+/// ones are the laboratory velocities less the streaming `shear_rate * y`.
+/// Shear heats the system, so a target `temperature` rescales peculiar velocities
+/// after every step. Shear viscosity is `-<pressure_xy> / shear_rate`.
+#[derive(Debug, Default)]
+pub struct Sllod {
+    temperature: Option<Real>,
+    pressure_xy: Cell<Real>,
+}
+
+impl Sllod {
+    /// Isokinetic SLLOD keeping the peculiar kinetic temperature at `temperature`.
+    pub fn temperature(mut self, temperature: Real) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Off-diagonal pressure between the flow and gradient axes after the last step.
+    pub fn pressure_xy(&self) -> Real {
+        self.pressure_xy.get()
+    }
+}
+
+impl<const D: usize> Integrator<D> for Sllod {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let shear_rate = boundaries.shear_rate();
+        let masses = state.atom_masses();
+        let dof = degrees_of_freedom(state);
+        let mut pos = state.get_pos();
+        let mut vel = state.get_vel();
+        let mut acc = state.get_acc();
+        let mut images = state.get_images();
+        stream(-shear_rate, &pos, &mut vel);
+        kick(delta_t / 2., shear_rate, &mut vel, &acc);
+        for (position, velocity) in pos.iter_mut().zip(vel.iter()) {
+            let y = position.components()[1] + delta_t / 2. * velocity.components()[1];
+            position.add_assign(delta_t * velocity);
+            add_along_flow(position, delta_t * shear_rate * y);
+        }
+        stream(shear_rate, &pos, &mut vel);
+        verlet::apply_boundary_conditions(boundaries, &mut pos, &mut vel, &mut images);
+        stream(-shear_rate, &pos, &mut vel);
+
+        verlet::compute_accelerations(potential_energy, &pos, &mut acc, &masses, boundaries);
+        kick(delta_t / 2., shear_rate, &mut vel, &acc);
+        if let Some(temperature) = self.temperature {
+            rescale(temperature, dof, &mut vel, &masses);
+        }
+        let pressure = pressure_tensor(&vel, &masses, potential_energy, boundaries.volume());
+        self.pressure_xy.set(pressure[0][1]);
+        stream(shear_rate, &pos, &mut vel);
+        Ok(())
+    }
+}
+
+fn add_along_flow<const D: usize>(v: &mut DVector<D>, dx: Real) {
+    let mut x = *v.components();
+    x[0] += dx;
+    *v = DVector::from(x);
+}
+
+/// Adds the streaming velocity `shear_rate * y` along the flow.
+fn stream<const D: usize>(shear_rate: Real, pos: &[DVector<D>], vel: &mut [DVector<D>]) {
+    for (position, velocity) in pos.iter().zip(vel.iter_mut()) {
+        add_along_flow(velocity, shear_rate * position.components()[1]);
+    }
+}
+
+/// Forces plus the SLLOD term `-shear_rate * p_y` along the flow.
+fn kick<const D: usize>(
+    delta_t: Real,
+    shear_rate: Real,
+    vel: &mut [DVector<D>],
+    acc: &[DVector<D>],
+) {
+    for (velocity, acceleration) in vel.iter_mut().zip(acc.iter()) {
+        velocity.add_assign(delta_t * acceleration);
+        let p_y = velocity.components()[1];
+        add_along_flow(velocity, -delta_t * shear_rate * p_y);
+    }
+}
+
+fn rescale<const D: usize>(temperature: Real, dof: Real, vel: &mut [DVector<D>], masses: &[Real]) {
+    let mvv_sum = mvv_sum(vel, masses);
+    if mvv_sum == 0. {
+        return;
+    }
+    let scale = (dof * temperature / mvv_sum).sqrt();
+    for velocity in vel.iter_mut() {
+        *velocity = scale * &*velocity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        initial_state::{cubic_lattice, randomize_vectors},
+        lennard_jones::LennardJones,
+        state::State,
+    };
+
+    fn sheared_box() -> LeesEdwards<3> {
+        let mut boundaries = LeesEdwards::new([4., 2., 4.], 0.5);
+        boundaries.sync(1.5);
+        boundaries
+    }
+
+    #[test]
+    fn crossing_shifts_position_and_velocity() {
+        let boundaries = sheared_box();
+        assert_eq!(1.5, boundaries.offset());
+        let mut position = DVector::from([0., 1.2, 0.]);
+        let mut velocity = DVector::from([0.3, 1., 0.]);
+        let image = boundaries.confine(&mut position, &mut velocity);
+        assert_eq!([0, 1, 0], image);
+        assert!((&position - &DVector::from([-1.5, -0.8, 0.])).length() < 1e-6);
+        assert!((&velocity - &DVector::from([-0.7, 1., 0.])).length() < 1e-6);
+    }
+
+    #[test]
+    fn minimum_image_across_sliding_boundary() {
+        let boundaries = sheared_box();
+        // Just below the top face and just above the bottom one, the image of
+        // the lower atom being shifted by the offset.
+        let top = DVector::from([1.4, 0.9, 0.]);
+        let bottom = DVector::from([0., -0.9, 0.]);
+        let mut dr = &top - &bottom;
+        boundaries.minimum_image(&mut dr);
+        assert!((&dr - &DVector::from([-0.1, -0.2, 0.])).length() < 1e-6);
+    }
+
+    #[test]
+    fn neighbour_list_follows_the_sliding_images() {
+        let (region, pos) = cubic_lattice::<3>(216, 0.8);
+        let mut boundaries = LeesEdwards::new(*region.dimensions(), 1.);
+        let all_pairs = LennardJones::default();
+        let neighbours = LennardJones::default().neighbour_list(0.4);
+        let mut acc = vec![DVector::default(); pos.len()];
+        let steps = 100;
+        for step in 0..steps {
+            boundaries.sync(step as Real * 0.01);
+            all_pairs.compute_forces(&pos, &mut acc, &boundaries);
+            neighbours.compute_forces(&pos, &mut acc, &boundaries);
+            let u_all = PotentialEnergy::<3>::u_sum(&all_pairs);
+            let u_neighbours = PotentialEnergy::<3>::u_sum(&neighbours);
+            assert!(
+                (u_all - u_neighbours).abs() <= 1e-4 * u_all.abs(),
+                "step {}: {} vs {}",
+                step,
+                u_all,
+                u_neighbours
+            );
+        }
+        let rebuilds = PotentialEnergy::<3>::neighbour_list_rebuilds(&neighbours).unwrap();
+        assert!(
+            rebuilds > 1 && rebuilds < steps / 2,
+            "{} rebuilds",
+            rebuilds
+        );
+    }
+
+    #[test]
+    fn sheared_fluid_has_positive_viscosity() {
+        let (region, pos) = cubic_lattice::<3>(64, 0.8);
+        let shear_rate = 1.;
+        let boundaries = LeesEdwards::new(*region.dimensions(), shear_rate);
+        let n_mol = pos.len();
+        let state = State::default();
+        *state.get_pos() = pos;
+        *state.get_vel() = vec![DVector::default(); n_mol];
+        *state.get_acc() = vec![DVector::default(); n_mol];
+        *state.get_images() = vec![[0; 3]; n_mol];
+        randomize_vectors(&mut state.get_vel(), 1.);
+        stream(shear_rate, &state.get_pos(), &mut state.get_vel());
+
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        let sllod = Sllod::default().temperature(1.);
+        let mut boundaries = boundaries;
+        let delta_t = 0.004;
+        sllod.prime(&state, &boundaries, &wca);
+        let mut pressure_xy = 0.;
+        let steps = 3000;
+        for step in 0..steps {
+            boundaries.sync((step + 1) as Real * delta_t);
+            sllod
+                .single_step(delta_t, &state, &boundaries, &wca)
+                .unwrap();
+            if step >= steps / 3 {
+                pressure_xy += sllod.pressure_xy();
+            }
+        }
+        let viscosity = -pressure_xy / (steps - steps / 3) as Real / shear_rate;
+        assert!(
+            viscosity > 0.3 && viscosity < 5.,
+            "viscosity = {}",
+            viscosity
+        );
+    }
+}
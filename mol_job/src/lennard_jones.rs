@@ -1,23 +1,78 @@
 #![allow(unused, dead_code)]
 
-use std::sync::atomic::Ordering;
-use crate::{boundaries::BoundaryConditions, potential::PotentialEnergy};
+use crate::{
+    boundaries::BoundaryConditions,
+    cell_list::{for_each_pair, CellList},
+    neighbour_list::NeighbourList,
+    potential::{AtomicReal, PotentialEnergy},
+    species::{Mixture, PairParameters, Species},
+    state::MolecularState,
+};
 use d_vector::{reset_array, DVector, Real};
-use atomic_float::AtomicF32;
+use std::sync::{atomic::Ordering, Mutex, RwLock};
 
+/// How `LennardJones` finds the pairs closer than `r_cut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PairSearch {
+    /// Every pair is checked: O(N²).
+    #[default]
+    AllPairs,
+    /// Atoms are binned into cells no smaller than `r_cut`: O(N).
+    /// Falls back to `AllPairs` if the boundaries have no box or the box is too small.
+    Cells,
+    /// Pairs within `r_cut + skin` are remembered between calls
+    /// and searched for again only when atoms have moved far enough.
+    NeighbourList,
+}
+
+/// What happens to the pair energy and force at the cutoff.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Truncation {
+    /// Both just drop to zero, so energy jumps whenever a pair crosses the cutoff.
+    Truncated,
+    /// Energy is shifted by its value at the cutoff and so is continuous;
+    /// the force still jumps. With the cutoff at 2^(1/6) σ this is the WCA potential.
+    #[default]
+    Shifted,
+    /// Force is shifted by its value at the cutoff and energy by the matching
+    /// linear term, so both go to zero continuously.
+    ForceShifted,
+    /// Energy is multiplied by a switching function going smoothly from 1 to 0
+    /// between `onset` times the cutoff and the cutoff itself.
+    Switched { onset: Real },
+}
+
+/// Lennard-Jones atoms of one or several species, which `attach` reads from
+/// the state; atoms the state has no species for are of species 0.
 #[derive(Debug)]
 pub struct LennardJones {
+    /// Largest cutoff of the mixture.
     r_cut: Real,
-    u_sum: AtomicF32,
-    v_sum: AtomicF32,
+    mixture: Mixture,
+    species: RwLock<Vec<usize>>,
+    truncation: Truncation,
+    tail_correction: bool,
+    search: PairSearch,
+    neighbours: Mutex<NeighbourList>,
+    u_sum: AtomicReal,
+    v_sum: AtomicReal,
+    /// Virial tensor flattened row by row.
+    w_sum: Mutex<Vec<Real>>,
 }
 
 impl Default for LennardJones {
     fn default() -> Self {
         Self {
             r_cut: 2.5,
-            u_sum: AtomicF32::new(0.0),
-            v_sum: AtomicF32::new(0.0),
+            mixture: Mixture::default(),
+            species: RwLock::default(),
+            truncation: Truncation::default(),
+            tail_correction: false,
+            search: PairSearch::default(),
+            neighbours: Mutex::default(),
+            u_sum: AtomicReal::new(0.0),
+            v_sum: AtomicReal::new(0.0),
+            w_sum: Mutex::default(),
         }
     }
 }
@@ -29,38 +84,38 @@ impl<const D: usize> PotentialEnergy<D> for LennardJones {
         acc: &mut [DVector<D>],
         boundaries: &dyn BoundaryConditions<D>,
     ) {
-        let n_mol = pos.len();
-        assert_eq!(n_mol, acc.len());
-
-        let rr_cut = self.r_cut * self.r_cut;
         reset_array(acc);
-        let mut u_sum = 0 as Real;
-        let mut v_sum = 0 as Real;
-
-        if n_mol > 0 {
-            for j1 in 0..(n_mol - 1) {
-                for j2 in (j1 + 1)..n_mol {
-                    let mut dr = &pos[j1] - &pos[j2];
-                    boundaries.wrap(&mut dr);
-                    let rr = dr.square_length();
-                    if rr < rr_cut {
-                        let rri = 1. / rr;
-                        let rri3 = rri * rri * rri;
+        self.add_forces(pos, acc, boundaries);
+    }
 
-                        let force_value = 48. * rri3 * (rri3 - 0.5) * rri;
-                        let force = force_value * dr;
+    fn add_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        assert_eq!(pos.len(), acc.len());
 
-                        acc[j1] += &force;
-                        acc[j2] -= &force;
+        #[cfg(not(feature = "parallel"))]
+        let (u_sum, v_sum, w_sum) = self.accumulate_pairs(pos, acc, boundaries);
+        #[cfg(feature = "parallel")]
+        let (u_sum, v_sum, w_sum) = self.accumulate_parallel(pos, acc, boundaries);
 
-                        u_sum += 4. * rri3 * (rri3 - 1.) + 1.;
-                        v_sum += force_value * rr;
-                    }
-                }
-            }
-        }
         self.u_sum.store(u_sum, Ordering::SeqCst);
         self.v_sum.store(v_sum, Ordering::SeqCst);
+        *self.w_sum.lock().unwrap() = w_sum;
+    }
+
+    fn attach(&self, state: &dyn MolecularState<D>) {
+        let species = state.get_species();
+        let n_species = self.mixture.n_species();
+        if let Some(s) = species.iter().find(|s| **s >= n_species) {
+            panic!(
+                "Species {} of the state is not in the mixture of {} species",
+                s, n_species
+            );
+        }
+        self.species.write().unwrap().clone_from(&species);
     }
 
     fn u_sum(&self) -> Real {
@@ -70,13 +125,668 @@ impl<const D: usize> PotentialEnergy<D> for LennardJones {
     fn virial_sum(&self) -> Real {
         self.v_sum.load(Ordering::SeqCst)
     }
+
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        let mut tensor = [[0.; D]; D];
+        for (row, w) in tensor
+            .iter_mut()
+            .zip(self.w_sum.lock().unwrap().chunks_exact(D))
+        {
+            row.copy_from_slice(w);
+        }
+        tensor
+    }
+
+    fn tail_correction(&self, n_mol: usize, volume: Real) -> (Real, Real) {
+        // Area of the unit sphere: the shell at distance r holds that times r^(D-1).
+        let sphere = match D {
+            2 => 2. * std::f64::consts::PI as Real,
+            3 => 4. * std::f64::consts::PI as Real,
+            _ => return (0., 0.),
+        };
+        if !self.tail_correction || volume <= 0. {
+            return (0., 0.);
+        }
+        let mut counts = vec![0; self.mixture.n_species()];
+        let species = self.species.read().unwrap();
+        for j in 0..n_mol {
+            counts[species.get(j).copied().unwrap_or_default()] += 1;
+        }
+        let d = D as i32;
+        let (mut u_tail, mut virial_tail) = (0 as Real, 0 as Real);
+        for (a, n_a) in counts.iter().enumerate() {
+            for (b, n_b) in counts.iter().enumerate() {
+                let pair = self.mixture.pair_parameters(a, b);
+                let s = pair.sigma / pair.r_cut;
+                // Integrals of r^(D-1) (σ/r)^n from the cutoff on, in units of σ^D.
+                let repulsion = s.powi(12 - d) / (12 - d) as Real;
+                let attraction = s.powi(6 - d) / (6 - d) as Real;
+                let weight = sphere / (2. * volume)
+                    * (n_a * n_b) as Real
+                    * 4.
+                    * pair.epsilon
+                    * pair.sigma.powi(d);
+                u_tail += weight * (repulsion - attraction);
+                virial_tail += weight * (12. * repulsion - 6. * attraction);
+                if self.truncation == Truncation::Shifted {
+                    // The shift taken off every pair within the cutoff.
+                    u_tail += weight * (s.powi(12 - d) - s.powi(6 - d)) / d as Real;
+                }
+            }
+        }
+        (u_tail, virial_tail)
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        let species = self.species.read().unwrap();
+        let mut u_sum = 0 as Real;
+        for (j, other) in pos.iter().enumerate() {
+            if j == atom {
+                continue;
+            }
+            let mut dr = position - other;
+            boundaries.minimum_image(&mut dr);
+            let pair = self.pair_parameters(&species, atom, j);
+            if let Some((_, u)) = pair_interaction(self.truncation, pair, dr.square_length()) {
+                u_sum += u;
+            }
+        }
+        u_sum
+    }
+
+    fn neighbour_list_rebuilds(&self) -> Option<usize> {
+        match self.search {
+            PairSearch::NeighbourList => Some(self.neighbours.lock().unwrap().rebuilds()),
+            _ => None,
+        }
+    }
 }
 
 impl LennardJones {
+    /// A single species with unit σ and ε, cut at `r_cut`.
     pub fn new(r_cut: Real) -> Self {
         Self {
             r_cut,
+            mixture: Mixture::lorentz_berthelot(vec![Species::default()], r_cut),
             ..Default::default()
         }
     }
+
+    /// Several species with their own parameters and cutoffs instead of one.
+    pub fn mixture(mut self, mixture: Mixture) -> Self {
+        self.r_cut = mixture.max_r_cut();
+        self.mixture = mixture;
+        self
+    }
+
+    pub fn truncation(mut self, truncation: Truncation) -> Self {
+        if let Truncation::Switched { onset } = truncation {
+            assert!(
+                (0. ..1.).contains(&onset),
+                "Switching must start before the cutoff"
+            );
+        }
+        self.truncation = truncation;
+        self.check_tail_correction();
+        self
+    }
+
+    /// Reports the energy and virial beyond the cutoff through `tail_correction`,
+    /// which then adds up to those of the plain potential. For
+    /// `Truncation::Shifted` the energy also gets back the shift of the pairs
+    /// within the cutoff. Other truncations are not supported.
+    pub fn tail_corrected(mut self) -> Self {
+        self.tail_correction = true;
+        self.check_tail_correction();
+        self
+    }
+
+    fn check_tail_correction(&self) {
+        assert!(
+            !self.tail_correction
+                || matches!(self.truncation, Truncation::Truncated | Truncation::Shifted),
+            "Tail corrections need a truncated or shifted potential"
+        );
+    }
+
+    /// Switches the pair search to cell subdivision.
+    pub fn cell_list(mut self) -> Self {
+        self.search = PairSearch::Cells;
+        self
+    }
+
+    /// Switches the pair search to a Verlet neighbour list with the given skin.
+    pub fn neighbour_list(mut self, skin: Real) -> Self {
+        self.search = PairSearch::NeighbourList;
+        self.neighbours = Mutex::new(NeighbourList::new(skin));
+        self
+    }
+
+    pub fn pair_search(&self) -> PairSearch {
+        self.search
+    }
+
+    fn accumulate_pairs<const D: usize>(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> (Real, Real, Vec<Real>) {
+        let mut u_sum = 0 as Real;
+        let mut v_sum = 0 as Real;
+        let mut w_sum = vec![0 as Real; D * D];
+        let species = self.species.read().unwrap();
+
+        let mut interact = |j1: usize, j2: usize| {
+            let mut dr = &pos[j1] - &pos[j2];
+            boundaries.minimum_image(&mut dr);
+            let rr = dr.square_length();
+            let pair = self.pair_parameters(&species, j1, j2);
+            if let Some((force_value, u)) = pair_interaction(self.truncation, pair, rr) {
+                add_dyad(&mut w_sum, force_value, &dr);
+                let force = force_value * dr;
+
+                acc[j1] += &force;
+                acc[j2] -= &force;
+
+                u_sum += u;
+                v_sum += force_value * rr;
+            }
+        };
+
+        if self.search == PairSearch::NeighbourList {
+            self.neighbours
+                .lock()
+                .unwrap()
+                .for_each_pair(pos, boundaries, self.r_cut, interact);
+        } else {
+            match self.build_cell_list(pos, boundaries) {
+                Some(cells) => cells.for_each_pair(interact),
+                None => for_each_pair(pos.len(), interact),
+            }
+        }
+        (u_sum, v_sum, w_sum)
+    }
+
+    /// Every atom sums up the forces from all of its neighbours by itself, so atoms
+    /// are shared between threads without any locking at the cost of evaluating
+    /// each pair twice. Energy and virial are reduced in atom order afterwards,
+    /// which keeps the result independent of the number of threads.
+    #[cfg(feature = "parallel")]
+    fn accumulate_parallel<const D: usize>(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> (Real, Real, Vec<Real>) {
+        use rayon::prelude::*;
+
+        let mut neighbour_list = self.neighbours.lock().unwrap();
+        let neighbours = match self.search {
+            PairSearch::NeighbourList => {
+                neighbour_list.refresh(pos, boundaries, self.r_cut);
+                Neighbours::List(neighbour_list.partners())
+            }
+            _ => match self.build_cell_list(pos, boundaries) {
+                Some(cells) => Neighbours::Cells(cells),
+                None => Neighbours::All(pos.len()),
+            },
+        };
+
+        let species = self.species.read().unwrap();
+        let sums: Vec<(Real, Real, Vec<Real>)> = acc
+            .par_iter_mut()
+            .enumerate()
+            .map(|(j1, acceleration)| {
+                let mut force = DVector::default();
+                let mut u_sum = 0 as Real;
+                let mut v_sum = 0 as Real;
+                let mut w_sum = vec![0 as Real; D * D];
+                neighbours.for_each(j1, |j2| {
+                    if j1 == j2 {
+                        return;
+                    }
+                    let mut dr = &pos[j1] - &pos[j2];
+                    boundaries.minimum_image(&mut dr);
+                    let rr = dr.square_length();
+                    let pair = self.pair_parameters(&species, j1, j2);
+                    if let Some((force_value, u)) = pair_interaction(self.truncation, pair, rr) {
+                        add_dyad(&mut w_sum, force_value / 2., &dr);
+                        force += &(force_value * dr);
+                        u_sum += u;
+                        v_sum += force_value * rr;
+                    }
+                });
+                *acceleration += &force;
+                (u_sum, v_sum, w_sum)
+            })
+            .collect();
+
+        let mut w_sum = vec![0 as Real; D * D];
+        let (mut u_sum, mut v_sum) = (0 as Real, 0 as Real);
+        for (du, dv, dw) in sums.iter() {
+            u_sum += du;
+            v_sum += dv;
+            for (w, dw) in w_sum.iter_mut().zip(dw) {
+                *w += dw;
+            }
+        }
+        (u_sum / 2., v_sum / 2., w_sum)
+    }
+
+    fn pair_parameters(&self, species: &[usize], j1: usize, j2: usize) -> &PairParameters {
+        let of = |j: usize| species.get(j).copied().unwrap_or_default();
+        self.mixture.pair_parameters(of(j1), of(j2))
+    }
+
+    fn build_cell_list<const D: usize>(
+        &self,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Option<CellList<D>> {
+        match self.search {
+            PairSearch::AllPairs | PairSearch::NeighbourList => None,
+            PairSearch::Cells => CellList::new(pos, boundaries.box_dimensions()?, self.r_cut),
+        }
+    }
+}
+
+/// Force value (to be multiplied by the separation) and energy of a pair
+/// at squared distance `rr`, or `None` beyond the cutoff.
+fn pair_interaction(
+    truncation: Truncation,
+    pair: &PairParameters,
+    rr: Real,
+) -> Option<(Real, Real)> {
+    let rr_cut = pair.r_cut * pair.r_cut;
+    if rr >= rr_cut {
+        return None;
+    }
+    let (force_value, u) = full_interaction(pair, rr);
+    Some(match truncation {
+        Truncation::Truncated => (force_value, u),
+        Truncation::Shifted => (force_value, u - full_interaction(pair, rr_cut).1),
+        Truncation::ForceShifted => {
+            let (force_value_cut, u_cut) = full_interaction(pair, rr_cut);
+            let (r, force_cut) = (rr.sqrt(), force_value_cut * pair.r_cut);
+            (
+                force_value - force_cut / r,
+                u - u_cut + (r - pair.r_cut) * force_cut,
+            )
+        }
+        Truncation::Switched { onset } => {
+            let rr_on = onset * onset * rr_cut;
+            if rr <= rr_on {
+                return Some((force_value, u));
+            }
+            // Switching function of the squared distance and its derivative.
+            let denominator = (rr_cut - rr_on).powi(3);
+            let switch = (rr_cut - rr).powi(2) * (rr_cut + 2. * rr - 3. * rr_on) / denominator;
+            let slope = 6. * (rr_cut - rr) * (rr_on - rr) / denominator;
+            (force_value * switch - 2. * u * slope, u * switch)
+        }
+    })
+}
+
+/// Force value and energy of the untruncated potential.
+fn full_interaction(pair: &PairParameters, rr: Real) -> (Real, Real) {
+    let rri = 1. / rr;
+    let sri = pair.sigma * pair.sigma * rri;
+    let sri3 = sri * sri * sri;
+    let force_value = 48. * pair.epsilon * sri3 * (sri3 - 0.5) * rri;
+    let u = 4. * pair.epsilon * sri3 * (sri3 - 1.);
+    (force_value, u)
+}
+
+/// Adds `factor` times the outer product of `dr` with itself to a flattened tensor.
+fn add_dyad<const D: usize>(tensor: &mut [Real], factor: Real, dr: &DVector<D>) {
+    let x = dr.components();
+    for (row, a) in tensor.chunks_exact_mut(D).zip(x) {
+        for (w, b) in row.iter_mut().zip(x) {
+            *w += factor * (a * b);
+        }
+    }
+}
+
+/// Neighbours of a single atom, whatever the pair search is.
+#[cfg(feature = "parallel")]
+enum Neighbours<'a, const D: usize> {
+    All(usize),
+    Cells(CellList<D>),
+    List(&'a [Vec<usize>]),
+}
+
+#[cfg(feature = "parallel")]
+impl<const D: usize> Neighbours<'_, D> {
+    fn for_each(&self, atom: usize, mut f: impl FnMut(usize)) {
+        match self {
+            Neighbours::All(n_mol) => (0..*n_mol).for_each(f),
+            Neighbours::Cells(cells) => cells.for_each_neighbour(atom, f),
+            Neighbours::List(partners) => partners[atom].iter().for_each(|j| f(*j)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initial_state::cubic_lattice;
+
+    #[test]
+    fn cell_list_matches_all_pairs() {
+        let (region, mut pos) = cubic_lattice::<3>(1000, 0.8);
+        for position in pos.iter_mut() {
+            *position += 0.2 * DVector::random_vector();
+            region.wrap(position);
+        }
+        let mut acc_all = vec![DVector::default(); pos.len()];
+        let mut acc_cells = vec![DVector::default(); pos.len()];
+
+        let all_pairs = LennardJones::default();
+        let cells = LennardJones::default().cell_list();
+        all_pairs.compute_forces(&pos, &mut acc_all, &region);
+        cells.compute_forces(&pos, &mut acc_cells, &region);
+
+        let u_all = PotentialEnergy::<3>::u_sum(&all_pairs);
+        let u_cells = PotentialEnergy::<3>::u_sum(&cells);
+        let v_all = PotentialEnergy::<3>::virial_sum(&all_pairs);
+        let v_cells = PotentialEnergy::<3>::virial_sum(&cells);
+        assert!(u_all < 0.);
+        assert!((u_all - u_cells).abs() <= 1e-4 * u_all.abs());
+        assert!((v_all - v_cells).abs() <= 1e-4 * v_all.abs());
+        let w_all = PotentialEnergy::<3>::virial_tensor(&all_pairs);
+        let trace: Real = (0..3).map(|i| w_all[i][i]).sum();
+        assert!((trace - v_all).abs() <= 1e-4 * v_all.abs());
+        assert_eq!(w_all[0][1], w_all[1][0]);
+        for (a, b) in acc_all.iter().zip(acc_cells.iter()) {
+            assert!((a - b).length() <= 1e-3 * (1. + a.length()));
+        }
+    }
+
+    #[test]
+    fn neighbour_list_follows_all_pairs() {
+        use crate::{initial_state::randomize_vectors, verlet};
+
+        let (region, pos) = cubic_lattice::<3>(343, 0.8);
+        let n_mol = pos.len();
+        let mut vel = vec![DVector::default(); n_mol];
+        randomize_vectors(&mut vel, 1.);
+        let all_pairs = LennardJones::default();
+        let neighbours = LennardJones::default().neighbour_list(0.4);
+        let mut images = vec![[0; 3]; n_mol];
+        let masses = vec![1.; n_mol];
+        let mut runs = [
+            (pos.clone(), vel.clone(), vec![DVector::default(); n_mol]),
+            (pos, vel, vec![DVector::default(); n_mol]),
+        ];
+
+        let steps = 100;
+        for _ in 0..steps {
+            for ((pos, vel, acc), potential) in runs.iter_mut().zip([
+                &all_pairs as &dyn PotentialEnergy<3>,
+                &neighbours as &dyn PotentialEnergy<3>,
+            ]) {
+                verlet::single_step(
+                    0.005,
+                    pos,
+                    vel,
+                    acc,
+                    &mut images,
+                    &masses,
+                    &region,
+                    potential,
+                );
+            }
+            let u_all = PotentialEnergy::<3>::u_sum(&all_pairs);
+            let u_neighbours = PotentialEnergy::<3>::u_sum(&neighbours);
+            assert!((u_all - u_neighbours).abs() <= 1e-3 * u_all.abs());
+        }
+
+        let rebuilds = PotentialEnergy::<3>::neighbour_list_rebuilds(&neighbours).unwrap();
+        assert!(rebuilds > 1);
+        assert!(rebuilds < steps / 2);
+        assert_eq!(
+            None,
+            PotentialEnergy::<3>::neighbour_list_rebuilds(&all_pairs)
+        );
+    }
+
+    #[test]
+    fn atom_energy_matches_total_difference() {
+        let (region, mut pos) = cubic_lattice::<3>(125, 0.8);
+        let mut acc = vec![DVector::default(); pos.len()];
+        let potential = LennardJones::default();
+        let moved = &pos[7] + &DVector::from([0.2, -0.1, 0.3]);
+        let du = PotentialEnergy::<3>::atom_energy(&potential, 7, &moved, &pos, &region)
+            - PotentialEnergy::<3>::atom_energy(&potential, 7, &pos[7], &pos, &region);
+
+        potential.compute_forces(&pos, &mut acc, &region);
+        let before = PotentialEnergy::<3>::u_sum(&potential);
+        pos[7] = moved;
+        potential.compute_forces(&pos, &mut acc, &region);
+        let after = PotentialEnergy::<3>::u_sum(&potential);
+        assert!((after - before - du).abs() <= 1e-3 * du.abs());
+    }
+
+    #[test]
+    fn cell_list_falls_back_for_small_box() {
+        let (region, pos) = cubic_lattice::<3>(27, 0.8);
+        let mut acc_all = vec![DVector::default(); pos.len()];
+        let mut acc_cells = vec![DVector::default(); pos.len()];
+        let all_pairs = LennardJones::default();
+        let cells = LennardJones::default().cell_list();
+        all_pairs.compute_forces(&pos, &mut acc_all, &region);
+        cells.compute_forces(&pos, &mut acc_cells, &region);
+        assert_eq!(
+            PotentialEnergy::<3>::u_sum(&all_pairs),
+            PotentialEnergy::<3>::u_sum(&cells)
+        );
+        assert_eq!(acc_all, acc_cells);
+    }
+
+    #[test]
+    fn binary_mixture_pair() {
+        use crate::{
+            boundaries::Region,
+            species::{Mixture, Species},
+            state::State,
+        };
+
+        let heavy = Species {
+            sigma: 1.2,
+            epsilon: 0.5,
+        };
+        let mixture = Mixture::lorentz_berthelot(vec![Species::default(), heavy], 2.5);
+        let potential = LennardJones::default().mixture(mixture);
+        let region = Region::new([10.; 3]);
+        let state = State::default();
+        state.insert_atom(DVector::from([0., 0., 0.]));
+        state.insert_atom(DVector::from([1.2, 0., 0.]));
+        state.get_species()[1] = 1;
+        potential.attach(&state);
+
+        let pos = state.get_pos();
+        let mut acc = vec![DVector::default(); 2];
+        potential.compute_forces(&pos, &mut acc, &region);
+        // σ = 1.1 and ε = √0.5 for the pair, shifted at 2.5 σ.
+        let lj = |r: Real| {
+            let sri6 = (1.1 / r).powi(6);
+            (0.5 as Real).sqrt() * 4. * sri6 * (sri6 - 1.)
+        };
+        let u = lj(1.2) - lj(2.75);
+        assert!((PotentialEnergy::<3>::u_sum(&potential) - u).abs() < 1e-5);
+        assert!((&acc[0] + &acc[1]).length() < 1e-5 * acc[0].length());
+        let du = PotentialEnergy::<3>::atom_energy(&potential, 1, &pos[1], &pos, &region);
+        assert!((du - u).abs() < 1e-5);
+    }
+
+    #[test]
+    fn truncations_are_consistent() {
+        let pair = PairParameters {
+            sigma: 1.,
+            epsilon: 1.,
+            r_cut: 2.5,
+        };
+        let u = |truncation, r: Real| pair_interaction(truncation, &pair, r * r).map(|(_, u)| u);
+        let force =
+            |truncation, r: Real| pair_interaction(truncation, &pair, r * r).map(|(f, _)| f * r);
+        let near_cut = 2.5 - 1e-3;
+        for truncation in [
+            Truncation::Truncated,
+            Truncation::Shifted,
+            Truncation::ForceShifted,
+            Truncation::Switched { onset: 0.8 },
+        ] {
+            assert_eq!(None, u(truncation, 2.5));
+            let jump = u(truncation, near_cut).unwrap().abs();
+            if truncation == Truncation::Truncated {
+                assert!(jump > 1e-2);
+            } else {
+                assert!(jump < 1e-4, "{:?}", truncation);
+            }
+            let force_jump = force(truncation, near_cut).unwrap().abs();
+            let smooth_force = matches!(
+                truncation,
+                Truncation::ForceShifted | Truncation::Switched { .. }
+            );
+            assert_eq!(smooth_force, force_jump < 1e-3, "{:?}", truncation);
+
+            for r in [0.95, 1.3, 1.9, 2.1, 2.4] {
+                let h = 1e-3;
+                let slope =
+                    (u(truncation, r + h).unwrap() - u(truncation, r - h).unwrap()) / (2. * h);
+                let f = force(truncation, r).unwrap();
+                assert!(
+                    (f + slope).abs() < 1e-2 * (1. + f.abs()),
+                    "{:?} at {}",
+                    truncation,
+                    r
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tail_correction_integrates_beyond_cutoff() {
+        use std::f64::consts::PI;
+
+        // Midpoint rule for the energy and virial of a uniform fluid beyond 2.5.
+        fn integrate(sphere: Real, d: i32, density: Real) -> (Real, Real) {
+            let (h, mut u, mut w) = (1e-3, 0., 0.);
+            for k in 0..200_000 {
+                let r = 2.5 + h * (k as Real + 0.5);
+                let sri6 = r.powi(-6);
+                let shell = density / 2. * sphere * r.powi(d - 1) * h;
+                u += shell * 4. * sri6 * (sri6 - 1.);
+                w += shell * 48. * sri6 * (sri6 - 0.5);
+            }
+            (u, w)
+        }
+
+        let potential = LennardJones::default()
+            .truncation(Truncation::Truncated)
+            .tail_corrected();
+        let (u, w) = PotentialEnergy::<3>::tail_correction(&potential, 100, 125.);
+        let (u_per_atom, w_per_atom) = integrate(4. * PI as Real, 3, 0.8);
+        assert!((u / 100. - u_per_atom).abs() < 1e-3 * u_per_atom.abs());
+        assert!((w / 100. - w_per_atom).abs() < 1e-3 * w_per_atom.abs());
+        assert!(u < 0. && w < 0.);
+
+        let (u, w) = PotentialEnergy::<2>::tail_correction(&potential, 50, 100.);
+        let (u_per_atom, w_per_atom) = integrate(2. * PI as Real, 2, 0.5);
+        assert!((u / 50. - u_per_atom).abs() < 1e-3 * u_per_atom.abs());
+        assert!((w / 50. - w_per_atom).abs() < 1e-3 * w_per_atom.abs());
+
+        let without = LennardJones::default();
+        assert_eq!(
+            (0., 0.),
+            PotentialEnergy::<3>::tail_correction(&without, 100, 125.)
+        );
+    }
+
+    #[test]
+    fn shifted_tail_correction_adds_back_the_shift() {
+        use std::f64::consts::PI;
+
+        let truncated = LennardJones::default()
+            .truncation(Truncation::Truncated)
+            .tail_corrected();
+        let shifted = LennardJones::default().tail_corrected();
+        let (u, w) = PotentialEnergy::<3>::tail_correction(&truncated, 100, 125.);
+        let (u_shifted, w_shifted) = PotentialEnergy::<3>::tail_correction(&shifted, 100, 125.);
+        // Half of the atoms times those within the cutoff of each, times u(r_c).
+        let sri6 = (2.5 as Real).powi(-6);
+        let u_cut = 4. * sri6 * (sri6 - 1.);
+        let within = 0.8 * 4. / 3. * PI as Real * (2.5 as Real).powi(3);
+        let shift = 100. / 2. * within * u_cut;
+        assert!((u_shifted - u - shift).abs() < 1e-3 * shift.abs());
+        assert_eq!(w, w_shifted);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tail_correction_rejects_force_shifted() {
+        LennardJones::default()
+            .tail_corrected()
+            .truncation(Truncation::ForceShifted);
+    }
+
+    #[test]
+    #[should_panic]
+    fn attach_rejects_species_beyond_the_mixture() {
+        let state = crate::state::State::default();
+        state.insert_atom(DVector::from([0., 0., 0.]));
+        state.get_species()[0] = 1;
+        PotentialEnergy::<3>::attach(&LennardJones::default(), &state);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_serial_and_is_deterministic() {
+        let (region, mut pos) = cubic_lattice::<3>(1000, 0.8);
+        for position in pos.iter_mut() {
+            *position += 0.2 * DVector::random_vector();
+            region.wrap(position);
+        }
+        for potential in [
+            LennardJones::default(),
+            LennardJones::default().cell_list(),
+            LennardJones::default().neighbour_list(0.3),
+        ] {
+            let mut acc_serial = vec![DVector::default(); pos.len()];
+            let (u_serial, v_serial, w_serial) =
+                potential.accumulate_pairs(&pos, &mut acc_serial, &region);
+
+            let run_on = |threads: usize| {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                let mut acc = vec![DVector::default(); pos.len()];
+                let sums = pool.install(|| potential.accumulate_parallel(&pos, &mut acc, &region));
+                (sums, acc)
+            };
+            let ((u_single, v_single, w_single), acc_single) = run_on(1);
+            let ((u_many, v_many, w_many), acc_many) = run_on(4);
+
+            assert_eq!(u_single, u_many);
+            assert_eq!(v_single, v_many);
+            assert_eq!(w_single, w_many);
+            for (a, b) in w_serial.iter().zip(w_many.iter()) {
+                assert!((a - b).abs() <= 1e-4 * v_serial.abs());
+            }
+            assert_eq!(acc_single, acc_many);
+            assert!((u_serial - u_many).abs() <= 1e-4 * u_serial.abs());
+            assert!((v_serial - v_many).abs() <= 1e-4 * v_serial.abs());
+            for (a, b) in acc_serial.iter().zip(acc_many.iter()) {
+                assert!((a - b).length() <= 1e-3 * (1. + a.length()));
+            }
+        }
+    }
 }
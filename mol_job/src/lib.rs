@@ -1,11 +1,27 @@
+pub mod barostat;
+pub mod bonded;
 pub mod boundaries;
+pub mod cell_list;
+pub mod constraints;
+pub mod ewald;
 pub mod initial_state;
+pub mod integrator;
 pub mod job;
+pub mod langevin;
+pub mod lees_edwards;
 pub mod lennard_jones;
+pub mod minimize;
+pub mod monte_carlo;
+pub mod neighbour_list;
+pub mod nose_hoover;
+pub mod pme;
 pub mod potential;
 pub mod prop;
+pub mod species;
 pub mod state;
+pub mod topology;
 pub mod track;
+pub mod triclinic;
 pub mod verlet;
 
 #[cfg(test)]
@@ -17,12 +33,294 @@ mod tests {
         use potential::NoInteraction;
         let mut j: Job<3> = JobSetup::build()
             .delta_t(1e-3)
-            .potential(NoInteraction::default())
+            .potential(NoInteraction)
             .job();
-        assert_eq!(0, j.run(100));
+        assert_eq!(Ok(0), j.run(100));
         assert_eq!(0.1, j.time_now())
     }
 
+    #[test]
+    fn thermo_props() {
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use prop::ThermoProps;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(125, 0.8);
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .random_vel(1.)
+            .potential(LennardJones::default())
+            .props(ThermoProps::new(20))
+            .job();
+        assert_eq!(Ok(0), j.run(40));
+    }
+
+    #[test]
+    fn swap_integrator() {
+        use integrator::{Integrator, Leapfrog};
+        use job::{Job, JobSetup};
+        use langevin::Langevin;
+        use lennard_jones::LennardJones;
+        use nose_hoover::NoseHoover;
+
+        fn run_with(integrator: impl Integrator<3> + 'static) {
+            let (boundaries, pos) = initial_state::cubic_lattice(27, 0.5);
+            let mut j: Job<3> = JobSetup::build()
+                .boundaries(boundaries)
+                .init_pos(pos)
+                .random_vel(1.)
+                .potential(LennardJones::default())
+                .integrator(integrator)
+                .job();
+            assert_eq!(Ok(0), j.run(20));
+        }
+
+        run_with(Leapfrog);
+        run_with(NoseHoover::new(1., 0.1));
+        run_with(Langevin::new(1., 1.).seed(1));
+    }
+
+    #[test]
+    fn sheared_job() {
+        use job::{Job, JobSetup};
+        use lees_edwards::{LeesEdwards, Sllod};
+        use lennard_jones::LennardJones;
+
+        let (region, pos) = initial_state::cubic_lattice(64, 0.8);
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(LeesEdwards::new(*region.dimensions(), 0.5))
+            .init_pos(pos)
+            .random_vel(1.)
+            .potential(LennardJones::default())
+            .integrator(Sllod::default().temperature(1.))
+            .job();
+        assert_eq!(Ok(0), j.run(200));
+        assert!(j.unwrapped_pos().iter().all(|r| r.length().is_finite()));
+    }
+
+    #[test]
+    fn kob_andersen() {
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use species::Mixture;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(125, 1.2);
+        let species = (0..pos.len()).map(|j| (j % 5 == 0) as usize).collect();
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .species(species)
+            .random_vel(1.)
+            .potential(
+                LennardJones::default()
+                    .mixture(Mixture::kob_andersen())
+                    .cell_list(),
+            )
+            .delta_t(0.002)
+            .job();
+        assert_eq!(Ok(0), j.run(50));
+        assert!(j.vel_sum().length() < 1e-3);
+    }
+
+    #[test]
+    fn charged_fluid() {
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use pme::Pme;
+        use potential::Composite;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(64, 0.5);
+        let charges = (0..pos.len())
+            .map(|j| {
+                if (j + j / 4 + j / 16) % 2 == 0 {
+                    1.
+                } else {
+                    -1.
+                }
+            })
+            .collect();
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .charges(charges)
+            .random_vel(1.)
+            .potential(
+                Composite::default()
+                    .term(LennardJones::new((2. as d_vector::Real).powf(1. / 6.)))
+                    .term(Pme::new(2., 1e-4)),
+            )
+            .job();
+        assert_eq!(Ok(0), j.run(50));
+        assert!(j.vel_sum().length() < 1e-2);
+        let u_terms = j.u_terms();
+        assert_eq!(2, u_terms.len());
+        assert!(u_terms[1] < 0.);
+        let u_sum = j.potential().u_sum();
+        assert!((u_terms[0] + u_terms[1] - u_sum).abs() < 1e-3 * u_sum.abs());
+    }
+
+    #[test]
+    fn bead_spring_polymers() {
+        use bonded::{Angles, Bonds};
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use potential::Composite;
+        use topology::Topology;
+
+        // Chains of five run along the rows of the lattice.
+        let (boundaries, pos) = initial_state::cubic_lattice(125, 0.85);
+        let topology = (0..25).fold(Topology::default(), |topology, c| {
+            topology.chain(5 * c..5 * c + 5)
+        });
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .topology(topology)
+            .random_vel(1.)
+            .potential(
+                Composite::default()
+                    .term(LennardJones::new((2. as d_vector::Real).powf(1. / 6.)))
+                    .term(Bonds::fene(30., 1.5))
+                    .term(Angles::cosine(1.5)),
+            )
+            .delta_t(0.002)
+            .job();
+        assert_eq!(Ok(0), j.run(100));
+        assert!(j.vel_sum().length() < 1e-3);
+    }
+
+    #[test]
+    fn rigid_dimers() {
+        use constraints::{Rattle, Shake};
+        use integrator::Integrator;
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use topology::Topology;
+
+        fn run_with(integrator: impl Integrator<3> + 'static) {
+            // Neighbours along the rows of the lattice are paired up.
+            let (boundaries, pos) = initial_state::cubic_lattice(64, 0.5);
+            let length = boundaries.dimensions()[2] / 4.;
+            let topology = (0..32).fold(Topology::default(), |topology, c| {
+                topology.constraint(2 * c, 2 * c + 1, length)
+            });
+            let mut j: Job<3> = JobSetup::build()
+                .boundaries(boundaries)
+                .init_pos(pos)
+                .topology(topology)
+                .random_vel(1.)
+                .potential(LennardJones::default())
+                .integrator(integrator)
+                .delta_t(0.005)
+                .job();
+            assert_eq!(Ok(0), j.run(100));
+            let pos = j.unwrapped_pos();
+            for pair in pos.chunks_exact(2) {
+                assert!(((&pair[0] - &pair[1]).length() - length).abs() < 1e-3);
+            }
+        }
+
+        run_with(Shake::default());
+        run_with(Rattle::default());
+    }
+
+    #[test]
+    fn failed_constraints_stop_the_run() {
+        use constraints::Shake;
+        use integrator::StepError;
+        use job::{Job, JobSetup};
+        use potential::NoInteraction;
+        use topology::Topology;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(8, 0.5);
+        let length = boundaries.dimensions()[2] / 2.;
+        let topology = (0..4).fold(Topology::default(), |topology, c| {
+            topology.constraint(2 * c, 2 * c + 1, length)
+        });
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .topology(topology)
+            .random_vel(1.)
+            .potential(NoInteraction)
+            .integrator(Shake::default().max_iterations(1))
+            .job();
+        match j.run(10) {
+            Err(StepError::Constraint(error)) => assert_eq!(1, error.iterations),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(1, j.step_count());
+    }
+
+    #[test]
+    fn npt() {
+        use barostat::Berendsen;
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use nose_hoover::NoseHoover;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(27, 0.5);
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .random_vel(1.)
+            .potential(LennardJones::default())
+            .integrator(NoseHoover::new(1., 0.1))
+            .barostat(Berendsen::new(1., 0.5))
+            .job();
+        let volume = j.volume();
+        assert_eq!(None, JobSetup::<3>::build().job().pressure());
+        assert_eq!(Ok(0), j.run(20));
+        assert!(j.pressure().is_some());
+        assert_ne!(volume, j.volume());
+    }
+
+    #[test]
+    fn barostat_stops_short_of_the_ewald_cutoff() {
+        use barostat::Berendsen;
+        use ewald::Ewald;
+        use integrator::StepError;
+        use job::{Job, JobSetup};
+
+        let (boundaries, pos) = initial_state::cubic_lattice(64, 0.5);
+        let r_cut = boundaries.dimensions()[0] / 2. - 0.01;
+        let charges = (0..pos.len())
+            .map(|j| if j % 2 == 0 { 1. } else { -1. })
+            .collect();
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .charges(charges)
+            .random_vel(1.)
+            .potential(Ewald::new(1., r_cut, 3))
+            .barostat(Berendsen::new(1000., 0.5))
+            .job();
+        match j.run(10) {
+            Err(StepError::BoxTooSmall { min_length, .. }) => assert_eq!(2. * r_cut, min_length),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(1, j.step_count());
+    }
+
+    #[test]
+    fn minimize_before_run() {
+        use job::{Job, JobSetup};
+        use lennard_jones::LennardJones;
+        use minimize::Fire;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(27, 1.5);
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .potential(LennardJones::default())
+            .minimize(Fire::default())
+            .random_vel(1.)
+            .job();
+        assert!(j.minimization().unwrap().converged);
+        assert_eq!(Ok(0), j.run(20));
+    }
+
     #[test]
     fn wrap() {
         use boundaries::{BoundaryConditions, Region};
@@ -30,25 +328,74 @@ mod tests {
         let region = Region::new([1., 5.]);
         let mut p = DVector::from([1.5, -4.]);
         region.wrap(&mut p);
-        assert_eq!(&[0.5, 1.], p.components());
+        assert_eq!(&[-0.5, 1.], p.components());
         let region = Region::new([1., 5.]);
         let mut p = DVector::from([0.2, -1.5]);
         region.wrap(&mut p);
         assert_eq!(&[0.2, -1.5], p.components());
     }
 
+    #[test]
+    fn unwrapped_pos() {
+        use job::{Job, JobSetup};
+        use potential::NoInteraction;
+
+        let (boundaries, pos) = initial_state::cubic_lattice(27, 1.);
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .init_pos(pos)
+            .random_vel(1.)
+            .potential(NoInteraction)
+            .job();
+        let start = j.unwrapped_pos();
+        j.run(1000).unwrap();
+        let travelled: Vec<d_vector::Real> = j
+            .unwrapped_pos()
+            .iter()
+            .zip(start.iter())
+            .map(|(now, then)| (now - then).length())
+            .collect();
+        assert!(travelled.iter().any(|d| *d > 3.));
+    }
+
+    #[test]
+    fn state_filled_directly() {
+        use job::{Job, JobSetup};
+        use potential::NoInteraction;
+        use state::{MolecularState, State};
+
+        let (boundaries, pos) = initial_state::cubic_lattice(8, 1.);
+        let state = State::default();
+        let n_mol = pos.len();
+        *state.get_pos() = pos.clone();
+        *state.get_vel() = vec![d_vector::DVector::from([1., 0., 0.]); n_mol];
+        *state.get_acc() = vec![d_vector::DVector::default(); n_mol];
+        let mut j: Job<3> = JobSetup::build()
+            .boundaries(boundaries)
+            .state(state)
+            .potential(NoInteraction)
+            .job();
+        assert_eq!(Ok(0), j.run(400));
+        let unwrapped = j.unwrapped_pos();
+        assert_eq!(n_mol, unwrapped.len());
+        for (now, then) in unwrapped.iter().zip(pos.iter()) {
+            assert!((now - then).length() > 1.9);
+        }
+    }
+
     #[test]
     fn cubic_lattice() {
         use job::{Job, JobSetup};
         use potential::NoInteraction;
 
         let (boundaries, pos) = initial_state::cubic_lattice(1000, 0.8);
+        assert_eq!(1000, pos.len());
         let mut j: Job<3> = JobSetup::build()
             .boundaries(boundaries)
             .init_pos(pos)
-            .potential(NoInteraction::default())
+            .potential(NoInteraction)
             .job();
-        assert_eq!(0, j.run(100));
+        assert_eq!(Ok(0), j.run(100));
         assert_eq!(0.5, j.time_now());
         assert!(j.vel_sum().length() < 1e-3);
     }
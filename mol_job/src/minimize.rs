@@ -0,0 +1,303 @@
+#![allow(unused, dead_code)]
+
+use crate::{boundaries::BoundaryConditions, potential::PotentialEnergy, verlet};
+use d_vector::{DVector, Real};
+use std::{fmt::Debug, ops::AddAssign};
+
+/// Outcome of an energy minimisation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Minimization {
+    pub iterations: usize,
+    pub u_sum: Real,
+    /// Largest force left on any atom.
+    pub max_force: Real,
+    pub converged: bool,
+}
+
+/// Moves atoms downhill in energy until the largest force falls below
+/// `force_tolerance` or `max_iterations` are spent.
+pub trait Minimizer<const D: usize>: Debug {
+    fn minimize(
+        &self,
+        pos: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Minimization;
+}
+
+/// Steepest descent with an adaptive step: grows the step while the energy
+/// goes down and halves it, undoing the move, when the energy goes up.
+#[derive(Debug)]
+pub struct SteepestDescent {
+    pub force_tolerance: Real,
+    pub max_iterations: usize,
+    /// Initial and largest displacement of the most strained atom per iteration.
+    pub max_step: Real,
+}
+
+impl Default for SteepestDescent {
+    fn default() -> Self {
+        Self {
+            force_tolerance: 1e-2,
+            max_iterations: 10000,
+            max_step: 0.1,
+        }
+    }
+}
+
+impl<const D: usize> Minimizer<D> for SteepestDescent {
+    fn minimize(
+        &self,
+        pos: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Minimization {
+        let mut force = vec![DVector::default(); pos.len()];
+        // Walls may bounce atoms back, but there are no velocities to reverse.
+        let mut still = vec![DVector::default(); pos.len()];
+        let mut u_sum = evaluate(pos, &mut still, &mut force, boundaries, potential_energy);
+        let mut step = self.max_step;
+        let mut iterations = 0;
+        while max_length(&force) > self.force_tolerance && iterations < self.max_iterations {
+            iterations += 1;
+            let scale = step / max_length(&force);
+            let previous = pos.to_vec();
+            for (position, f) in pos.iter_mut().zip(force.iter()) {
+                position.add_assign(scale * f);
+            }
+            let mut trial_force = vec![DVector::default(); pos.len()];
+            let trial_u_sum = evaluate(
+                pos,
+                &mut still,
+                &mut trial_force,
+                boundaries,
+                potential_energy,
+            );
+            if trial_u_sum < u_sum {
+                u_sum = trial_u_sum;
+                force = trial_force;
+                step = (1.2 * step).min(self.max_step);
+            } else {
+                pos.clone_from_slice(&previous);
+                step /= 2.;
+            }
+        }
+        potential_energy.compute_forces(pos, &mut force, boundaries);
+        summary(iterations, &force, potential_energy, self.force_tolerance)
+    }
+}
+
+/// Fast inertial relaxation engine (Bitzek et al., 2006): damped dynamics
+/// whose velocities are steered along the forces and zeroed on uphill moves.
+#[derive(Debug)]
+pub struct Fire {
+    pub force_tolerance: Real,
+    pub max_iterations: usize,
+    pub delta_t: Real,
+    pub max_delta_t: Real,
+    /// Takes over, with the same `force_tolerance`, from wherever FIRE gets
+    /// to when it runs out of iterations.
+    pub fallback: Option<SteepestDescent>,
+}
+
+impl Default for Fire {
+    fn default() -> Self {
+        Self {
+            force_tolerance: 1e-2,
+            max_iterations: 10000,
+            delta_t: 0.002,
+            max_delta_t: 0.02,
+            fallback: Some(SteepestDescent::default()),
+        }
+    }
+}
+
+const FIRE_N_MIN: usize = 5;
+const FIRE_F_INC: Real = 1.1;
+const FIRE_F_DEC: Real = 0.5;
+const FIRE_ALPHA_START: Real = 0.1;
+const FIRE_F_ALPHA: Real = 0.99;
+/// No atom moves further than this in one iteration, which keeps
+/// overlapping atoms from flying off.
+const FIRE_MAX_MOVE: Real = 0.1;
+
+impl<const D: usize> Minimizer<D> for Fire {
+    fn minimize(
+        &self,
+        pos: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Minimization {
+        let n_mol = pos.len();
+        let mut vel = vec![DVector::default(); n_mol];
+        let mut force = vec![DVector::default(); n_mol];
+        evaluate(pos, &mut vel, &mut force, boundaries, potential_energy);
+
+        let mut delta_t = self.delta_t;
+        let mut alpha = FIRE_ALPHA_START;
+        let mut downhill_steps = 0;
+        let mut iterations = 0;
+        while max_length(&force) > self.force_tolerance && iterations < self.max_iterations {
+            iterations += 1;
+            let power: Real = vel.iter().zip(force.iter()).map(|(v, f)| v * f).sum();
+            if power > 0. {
+                let v_norm = vel.iter().map(|v| v.square_length()).sum::<Real>().sqrt();
+                let f_norm = force.iter().map(|f| f.square_length()).sum::<Real>().sqrt();
+                for (v, f) in vel.iter_mut().zip(force.iter()) {
+                    *v = (1. - alpha) * &*v + (alpha * v_norm / f_norm) * f;
+                }
+                downhill_steps += 1;
+                if downhill_steps > FIRE_N_MIN {
+                    delta_t = (delta_t * FIRE_F_INC).min(self.max_delta_t);
+                    alpha *= FIRE_F_ALPHA;
+                }
+            } else {
+                delta_t *= FIRE_F_DEC;
+                alpha = FIRE_ALPHA_START;
+                downhill_steps = 0;
+                for v in vel.iter_mut() {
+                    *v = DVector::default();
+                }
+            }
+
+            for (v, f) in vel.iter_mut().zip(force.iter()) {
+                v.add_assign(delta_t * f);
+            }
+            let max_move = delta_t * max_length(&vel);
+            let limit = if max_move > FIRE_MAX_MOVE {
+                FIRE_MAX_MOVE / max_move
+            } else {
+                1.
+            };
+            for (position, v) in pos.iter_mut().zip(vel.iter()) {
+                position.add_assign((limit * delta_t) * v);
+            }
+            evaluate(pos, &mut vel, &mut force, boundaries, potential_energy);
+        }
+        let result = summary(iterations, &force, potential_energy, self.force_tolerance);
+        match &self.fallback {
+            Some(fallback) if !result.converged => {
+                let descent = SteepestDescent {
+                    force_tolerance: self.force_tolerance,
+                    ..*fallback
+                };
+                let rest = Minimizer::<D>::minimize(&descent, pos, boundaries, potential_energy);
+                Minimization {
+                    iterations: iterations + rest.iterations,
+                    ..rest
+                }
+            }
+            _ => result,
+        }
+    }
+}
+
+fn evaluate<const D: usize>(
+    pos: &mut [DVector<D>],
+    vel: &mut [DVector<D>],
+    force: &mut [DVector<D>],
+    boundaries: &dyn BoundaryConditions<D>,
+    potential_energy: &dyn PotentialEnergy<D>,
+) -> Real {
+    // Minimisation is not a trajectory, so boxes crossed are not kept.
+    let mut images = vec![[0; D]; pos.len()];
+    verlet::apply_boundary_conditions(boundaries, pos, vel, &mut images);
+    potential_energy.compute_forces(pos, force, boundaries);
+    potential_energy.u_sum()
+}
+
+fn max_length<const D: usize>(vectors: &[DVector<D>]) -> Real {
+    vectors
+        .iter()
+        .map(|v| v.length())
+        .fold(0 as Real, Real::max)
+}
+
+fn summary<const D: usize>(
+    iterations: usize,
+    force: &[DVector<D>],
+    potential_energy: &dyn PotentialEnergy<D>,
+    force_tolerance: Real,
+) -> Minimization {
+    let max_force = max_length(force);
+    Minimization {
+        iterations,
+        u_sum: potential_energy.u_sum(),
+        max_force,
+        converged: max_force <= force_tolerance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, initial_state::cubic_lattice, lennard_jones::LennardJones};
+
+    fn squeezed() -> (Region<3>, Vec<DVector<3>>, Real) {
+        let (region, mut pos) = cubic_lattice::<3>(64, 0.8);
+        let next = pos[1].clone();
+        pos[0] = &next + &DVector::from([0.3, 0., 0.]);
+        let mut acc = vec![DVector::default(); pos.len()];
+        let potential = LennardJones::default();
+        potential.compute_forces(&pos, &mut acc, &region);
+        let u_sum = PotentialEnergy::<3>::u_sum(&potential);
+        (region, pos, u_sum)
+    }
+
+    #[test]
+    fn fire_removes_overlap() {
+        let (region, mut pos, initial) = squeezed();
+        let potential = LennardJones::default();
+        let result = Fire::default().minimize(&mut pos, &region, &potential);
+        assert!(result.converged, "{:?}", result);
+        assert!(result.max_force <= 1e-2);
+        assert!(result.u_sum < initial);
+        assert!(result.u_sum.is_finite());
+    }
+
+    #[test]
+    fn steepest_descent_removes_overlap() {
+        let (region, mut pos, _) = squeezed();
+        // Descent stalls on the step the shifted cut-off leaves in the full
+        // potential, so use the continuous WCA one.
+        let potential = LennardJones::new((2 as Real).powf(1. / 6.));
+        let mut acc = vec![DVector::default(); pos.len()];
+        potential.compute_forces(&pos, &mut acc, &region);
+        let initial = PotentialEnergy::<3>::u_sum(&potential);
+        let minimizer = SteepestDescent {
+            force_tolerance: 0.1,
+            ..Default::default()
+        };
+        let result = minimizer.minimize(&mut pos, &region, &potential);
+        assert!(result.converged, "{:?}", result);
+        assert!(result.u_sum < initial);
+        assert!(result.u_sum.is_finite());
+    }
+
+    #[test]
+    fn iteration_cap() {
+        let (region, mut pos, initial) = squeezed();
+        let minimizer = Fire {
+            max_iterations: 3,
+            fallback: None,
+            ..Default::default()
+        };
+        let result = minimizer.minimize(&mut pos, &region, &LennardJones::default());
+        assert_eq!(3, result.iterations);
+        assert!(!result.converged);
+    }
+
+    #[test]
+    fn fire_falls_back_to_steepest_descent() {
+        let (region, mut pos, _) = squeezed();
+        let potential = LennardJones::new((2 as Real).powf(1. / 6.));
+        let minimizer = Fire {
+            force_tolerance: 0.1,
+            max_iterations: 3,
+            ..Default::default()
+        };
+        let result = minimizer.minimize(&mut pos, &region, &potential);
+        assert!(result.converged, "{:?}", result);
+        assert!(result.iterations > 3);
+    }
+}
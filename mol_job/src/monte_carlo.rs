@@ -0,0 +1,453 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::{BoundaryConditions, Region},
+    lennard_jones::LennardJones,
+    potential::PotentialEnergy,
+    prop::{Props, TrivialProps},
+    state::{MolecularState, State},
+    topology::Topology,
+};
+use d_vector::{DVector, Real};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+/// Metropolis Monte Carlo in the NVT ensemble with single-atom displacement moves,
+/// or in the µVT ensemble once `grand_canonical` adds insertions and deletions.
+///
+/// One sweep attempts as many moves as there are atoms. After every sweep the
+/// forces are recomputed for the virial, velocities are drawn from the
+/// Maxwell–Boltzmann distribution and `Props` are evaluated as in `Job::run`.
+#[derive(Debug)]
+pub struct MonteCarlo<const D: usize> {
+    state: Box<dyn MolecularState<D>>,
+    boundaries: Box<dyn BoundaryConditions<D>>,
+    potential: Box<dyn PotentialEnergy<D>>,
+    props: Box<dyn Props<D>>,
+    temperature: Real,
+    max_displacement: Real,
+    target_acceptance: Option<Real>,
+    tune_interval: usize,
+    chemical_potential: Option<Real>,
+    exchanges: usize,
+    rng: StdRng,
+    u_sum: Real,
+    sweep_count: usize,
+    moves: Acceptance,
+    since_tune: Acceptance,
+    insertions: Acceptance,
+    deletions: Acceptance,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Acceptance {
+    attempted: usize,
+    accepted: usize,
+}
+
+impl Acceptance {
+    fn ratio(&self) -> Real {
+        if self.attempted == 0 {
+            return 0.;
+        }
+        self.accepted as Real / self.attempted as Real
+    }
+}
+
+impl<const D: usize> MonteCarlo<D> {
+    /// Lennard-Jones atoms in the default box, the step size tuned towards
+    /// half of the moves being accepted. Seeded from the operating system.
+    pub fn new(temperature: Real) -> Self {
+        Self {
+            state: Box::new(State::default()),
+            boundaries: Box::new(Region::new([50.; D])),
+            potential: Box::new(LennardJones::default()),
+            props: Box::new(TrivialProps),
+            temperature,
+            max_displacement: 0.1,
+            target_acceptance: Some(0.5),
+            tune_interval: 10,
+            chemical_potential: None,
+            exchanges: 0,
+            rng: StdRng::from_entropy(),
+            u_sum: 0.,
+            sweep_count: 0,
+            moves: Acceptance::default(),
+            since_tune: Acceptance::default(),
+            insertions: Acceptance::default(),
+            deletions: Acceptance::default(),
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn state(mut self, state: impl MolecularState<D> + 'static) -> Self {
+        self.state = Box::new(state);
+        self
+    }
+
+    pub fn boundaries(mut self, boundaries: impl BoundaryConditions<D> + 'static) -> Self {
+        self.boundaries = Box::new(boundaries);
+        self
+    }
+
+    pub fn potential(mut self, potential: impl PotentialEnergy<D> + 'static) -> Self {
+        self.potential = Box::new(potential);
+        self
+    }
+
+    pub fn props(mut self, props: impl Props<D> + 'static) -> Self {
+        self.props = Box::new(props);
+        self
+    }
+
+    pub fn init_pos(mut self, pos: Vec<DVector<D>>) -> Self {
+        let n_mol = pos.len();
+        *self.state.get_pos() = pos;
+        *self.state.get_vel() = vec![DVector::default(); n_mol];
+        *self.state.get_acc() = vec![DVector::default(); n_mol];
+        *self.state.get_images() = vec![[0; D]; n_mol];
+        *self.state.get_species() = vec![0; n_mol];
+        *self.state.get_charges() = vec![0.; n_mol];
+        *self.state.get_topology() = Topology::default();
+        self
+    }
+
+    /// Largest displacement along each axis to start from.
+    pub fn max_displacement(mut self, max_displacement: Real) -> Self {
+        self.max_displacement = max_displacement;
+        self
+    }
+
+    /// Every `interval` sweeps the maximum displacement is scaled
+    /// to bring the acceptance ratio closer to `ratio`.
+    pub fn target_acceptance(mut self, ratio: Real, interval: usize) -> Self {
+        assert!(interval > 0, "Step size tuning interval must be positive");
+        self.target_acceptance = Some(ratio);
+        self.tune_interval = interval;
+        self
+    }
+
+    /// Keeps the maximum displacement as it is, e.g. for production after equilibration.
+    pub fn fixed_step(mut self) -> Self {
+        self.target_acceptance = None;
+        self
+    }
+
+    /// Lets the number of atoms fluctuate at the given chemical potential:
+    /// every sweep also attempts `exchanges` insertions or deletions, each
+    /// equally likely. The thermal de Broglie wavelength is the unit of length,
+    /// so an ideal gas settles at density `exp(chemical_potential / temperature)`.
+    pub fn grand_canonical(mut self, chemical_potential: Real, exchanges: usize) -> Self {
+        self.chemical_potential = Some(chemical_potential);
+        self.exchanges = exchanges;
+        self
+    }
+
+    pub fn run(&mut self, sweeps: usize) {
+        self.potential.attach(self.state.as_ref());
+        if self.sweep_count == 0 {
+            self.u_sum = self.total_energy();
+        }
+        for _ in 0..sweeps {
+            self.sweep();
+            self.sweep_count += 1;
+            if let Some(target) = self.target_acceptance {
+                if self.sweep_count.is_multiple_of(self.tune_interval) {
+                    self.tune(target);
+                }
+            }
+            self.u_sum = self.total_energy();
+            self.draw_velocities();
+            self.update_props();
+        }
+    }
+
+    fn sweep(&mut self) {
+        let n_mol = self.state.get_pos().len();
+        for _ in 0..n_mol {
+            let accepted = self.try_displacement();
+            for acceptance in [&mut self.moves, &mut self.since_tune] {
+                acceptance.attempted += 1;
+                acceptance.accepted += accepted as usize;
+            }
+        }
+        if let Some(chemical_potential) = self.chemical_potential {
+            for _ in 0..self.exchanges {
+                if self.rng.gen::<bool>() {
+                    let accepted = self.try_insertion(chemical_potential);
+                    self.insertions.attempted += 1;
+                    self.insertions.accepted += accepted as usize;
+                } else {
+                    let accepted = self.try_deletion(chemical_potential);
+                    self.deletions.attempted += 1;
+                    self.deletions.accepted += accepted as usize;
+                }
+            }
+        }
+    }
+
+    fn try_displacement(&mut self) -> bool {
+        let mut pos = self.state.get_pos();
+        let atom = self.rng.gen_range(0..pos.len());
+        let mut shift = [0 as Real; D];
+        for s in shift.iter_mut() {
+            *s = self.max_displacement * self.rng.gen_range(-1. ..1.);
+        }
+        let mut trial = &pos[atom] + &DVector::from(shift);
+        let crossed = self.boundaries.confine(&mut trial, &mut DVector::default());
+
+        let boundaries = self.boundaries.as_ref();
+        let du = self.potential.atom_energy(atom, &trial, &pos, boundaries)
+            - self
+                .potential
+                .atom_energy(atom, &pos[atom], &pos, boundaries);
+        let accepted = du <= 0. || self.rng.gen::<Real>() < (-du / self.temperature).exp();
+        if accepted {
+            pos[atom] = trial;
+            for (n, c) in self.state.get_images()[atom].iter_mut().zip(crossed) {
+                *n += c;
+            }
+            self.u_sum += du;
+        }
+        accepted
+    }
+
+    fn try_insertion(&mut self, chemical_potential: Real) -> bool {
+        let mut fractional = [0 as Real; D];
+        for s in fractional.iter_mut() {
+            *s = self.rng.gen_range(-0.5..0.5);
+        }
+        let position = self.boundaries.at_fractional(&fractional);
+
+        let pos = self.state.get_pos();
+        let n_mol = pos.len();
+        let du = self
+            .potential
+            .atom_energy(n_mol, &position, &pos, self.boundaries.as_ref());
+        drop(pos);
+        let acceptance = self.boundaries.volume() / (n_mol + 1) as Real
+            * ((chemical_potential - du) / self.temperature).exp();
+        let accepted = self.rng.gen::<Real>() < acceptance;
+        if accepted {
+            self.state.insert_atom(position);
+            self.potential.attach(self.state.as_ref());
+            self.u_sum += du;
+        }
+        accepted
+    }
+
+    fn try_deletion(&mut self, chemical_potential: Real) -> bool {
+        let pos = self.state.get_pos();
+        let n_mol = pos.len();
+        if n_mol == 0 {
+            return false;
+        }
+        let atom = self.rng.gen_range(0..n_mol);
+        let du = -self
+            .potential
+            .atom_energy(atom, &pos[atom], &pos, self.boundaries.as_ref());
+        drop(pos);
+        let acceptance = n_mol as Real / self.boundaries.volume()
+            * (-(chemical_potential + du) / self.temperature).exp();
+        let accepted = self.rng.gen::<Real>() < acceptance;
+        if accepted {
+            self.state.remove_atom(atom);
+            self.potential.attach(self.state.as_ref());
+            self.u_sum += du;
+        }
+        accepted
+    }
+
+    fn tune(&mut self, target: Real) {
+        let factor = (self.since_tune.ratio() / target).clamp(0.5, 1.5);
+        self.max_displacement *= factor;
+        // Cells without an axis-aligned box go by a cube of the same volume.
+        let half_box = match self.boundaries.box_dimensions() {
+            Some(dimensions) => dimensions.iter().cloned().fold(Real::INFINITY, Real::min) / 2.,
+            None => self.boundaries.volume().powf(1. / D as Real) / 2.,
+        };
+        self.max_displacement = self.max_displacement.min(half_box);
+        self.since_tune = Acceptance::default();
+    }
+
+    /// Energy of the whole configuration; also leaves the virial in the potential.
+    fn total_energy(&self) -> Real {
+        self.potential.compute_forces(
+            &self.state.get_pos(),
+            &mut self.state.get_acc(),
+            self.boundaries.as_ref(),
+        );
+        self.potential.u_sum()
+    }
+
+    fn draw_velocities(&mut self) {
+        let masses = self.state.atom_masses();
+        for (velocity, mass) in self.state.get_vel().iter_mut().zip(masses) {
+            let scale = (self.temperature / mass).sqrt();
+            let mut components = [0 as Real; D];
+            for c in components.iter_mut() {
+                *c = scale * self.rng.sample::<Real, _>(StandardNormal);
+            }
+            *velocity = DVector::from(components);
+        }
+    }
+
+    fn update_props(&self) {
+        self.props.eval_props(
+            self.potential.as_ref(),
+            self.boundaries.as_ref(),
+            self.state.as_ref(),
+        );
+        self.props.accum_props();
+        if self.props.need_avg(self.sweep_count) {
+            self.props.avg_props();
+            self.props.summarize();
+            self.props.reset();
+        }
+    }
+
+    pub fn sweep_count(&self) -> usize {
+        self.sweep_count
+    }
+
+    /// Potential energy of the current configuration.
+    pub fn u_sum(&self) -> Real {
+        self.u_sum
+    }
+
+    /// Fraction of all displacement moves accepted so far.
+    pub fn acceptance_ratio(&self) -> Real {
+        self.moves.ratio()
+    }
+
+    pub fn n_mol(&self) -> usize {
+        self.state.get_pos().len()
+    }
+
+    /// Fraction of insertions and deletions accepted so far.
+    pub fn exchange_acceptance_ratio(&self) -> (Real, Real) {
+        (self.insertions.ratio(), self.deletions.ratio())
+    }
+
+    pub fn current_max_displacement(&self) -> Real {
+        self.max_displacement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{initial_state::cubic_lattice, potential::NoInteraction, prop::ThermoProps};
+
+    #[test]
+    fn ideal_gas_accepts_everything() {
+        let (region, pos) = cubic_lattice::<3>(64, 0.5);
+        let mut mc = MonteCarlo::new(1.5)
+            .seed(1)
+            .boundaries(region)
+            .init_pos(pos)
+            .potential(NoInteraction);
+        let props = ThermoProps::<3>::new(100);
+        for _ in 0..100 {
+            mc.run(1);
+            props.eval_props(
+                mc.potential.as_ref(),
+                mc.boundaries.as_ref(),
+                mc.state.as_ref(),
+            );
+            props.accum_props();
+        }
+        props.avg_props();
+        let summary = props.summary().unwrap();
+        assert!((summary.temperature.mean - 1.5).abs() < 0.05);
+        assert!((summary.pressure.mean - 0.5 * 1.5).abs() < 0.05);
+        assert_eq!(100, mc.sweep_count());
+        assert_eq!(1., mc.acceptance_ratio());
+        assert_eq!(
+            mc.current_max_displacement(),
+            mc.boundaries.box_dimensions().unwrap()[0] / 2.
+        );
+    }
+
+    fn lj_fluid(seed: u64) -> MonteCarlo<3> {
+        let (region, pos) = cubic_lattice::<3>(64, 0.6);
+        MonteCarlo::new(1.5)
+            .seed(seed)
+            .boundaries(region)
+            .init_pos(pos)
+            .potential(LennardJones::default())
+            .target_acceptance(0.4, 5)
+    }
+
+    #[test]
+    fn tunes_step_and_tracks_energy() {
+        let mut mc = lj_fluid(3);
+        mc.run(200);
+        assert!((mc.moves.ratio() - 0.4).abs() < 0.1, "{:?}", mc.moves);
+
+        mc.sweep();
+        let tracked = mc.u_sum();
+        assert!((tracked - mc.total_energy()).abs() <= 1e-3 * tracked.abs());
+    }
+
+    #[test]
+    fn ideal_gas_at_chemical_potential() {
+        let temperature = 1.5;
+        let mut mc = MonteCarlo::<3>::new(temperature)
+            .seed(5)
+            .boundaries(Region::new([5.; 3]))
+            .potential(NoInteraction)
+            .grand_canonical(temperature * (0.4 as Real).ln(), 50);
+        mc.run(100);
+        let mut n_sum = 0;
+        for _ in 0..400 {
+            mc.run(1);
+            n_sum += mc.n_mol();
+        }
+        let density = n_sum as Real / 400. / 125.;
+        assert!((density - 0.4).abs() < 0.02, "density = {}", density);
+        let (insertions, deletions) = mc.exchange_acceptance_ratio();
+        assert!(insertions > 0. && deletions > 0.);
+    }
+
+    #[test]
+    fn inserts_into_a_skewed_cell() {
+        use crate::triclinic::Triclinic;
+
+        let temperature = 1.5;
+        let cell = || Triclinic::new([[5., 0., 0.], [2., 5., 0.], [0., 1., 5.]]);
+        let mut mc = MonteCarlo::<3>::new(temperature)
+            .seed(6)
+            .boundaries(cell())
+            .potential(NoInteraction)
+            .grand_canonical(temperature * (0.4 as Real).ln(), 50);
+        mc.run(100);
+        let mut n_sum = 0;
+        for _ in 0..400 {
+            mc.run(1);
+            n_sum += mc.n_mol();
+        }
+        let density = n_sum as Real / 400. / 125.;
+        assert!((density - 0.4).abs() < 0.02, "density = {}", density);
+        for position in mc.state.get_pos().iter() {
+            let s = cell().fractional(position);
+            assert!(s.iter().all(|s| (-0.5..0.5).contains(s)), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn reproducible_with_seed() {
+        let run = |seed| {
+            let mut mc = lj_fluid(seed);
+            mc.run(20);
+            let pos = mc.state.get_pos().clone();
+            (mc.u_sum(), pos)
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7).1, run(8).1);
+    }
+}
@@ -0,0 +1,171 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    cell_list::{for_each_pair, CellList},
+};
+use d_vector::{DVector, Real};
+
+/// Pairs closer than `r_cut + skin`, kept between steps and rebuilt
+/// only once some atom has moved further than half the skin. Under
+/// Lees–Edwards boundaries the slide of the offset uses up skin as well.
+#[derive(Debug, Default)]
+pub struct NeighbourList {
+    skin: Real,
+    pairs: Vec<(usize, usize)>,
+    partners: Vec<Vec<usize>>,
+    reference: Vec<Real>,
+    reference_offset: Real,
+    rebuilds: usize,
+}
+
+impl NeighbourList {
+    pub fn new(skin: Real) -> Self {
+        Self {
+            skin,
+            ..Default::default()
+        }
+    }
+
+    pub fn skin(&self) -> Real {
+        self.skin
+    }
+
+    pub fn pairs(&self) -> &[(usize, usize)] {
+        &self.pairs
+    }
+
+    /// Both-way lists of partners for every atom, built together with `pairs`.
+    pub fn partners(&self) -> &[Vec<usize>] {
+        &self.partners
+    }
+
+    /// Number of times the list has been built so far.
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+
+    pub fn needs_rebuild<const D: usize>(
+        &self,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> bool {
+        if self.reference.len() != D * pos.len() {
+            return true;
+        }
+        // Pairs across the sliding faces have moved apart by the slide on top.
+        let slide = (boundaries.shear_offset() - self.reference_offset).abs();
+        if slide >= self.skin {
+            return true;
+        }
+        let limit = (self.skin - slide) / 2.;
+        pos.iter()
+            .zip(self.reference.chunks_exact(D))
+            .any(|(position, reference)| {
+                let reference: [Real; D] = reference.try_into().unwrap();
+                let mut dr = position - &DVector::from(reference);
+                boundaries.minimum_image(&mut dr);
+                dr.square_length() > limit * limit
+            })
+    }
+
+    pub fn rebuild<const D: usize>(
+        &mut self,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        r_cut: Real,
+    ) {
+        let r_list = r_cut + self.skin;
+        let pairs = &mut self.pairs;
+        pairs.clear();
+        let add = |j1: usize, j2: usize| {
+            let mut dr = &pos[j1] - &pos[j2];
+            boundaries.minimum_image(&mut dr);
+            if dr.square_length() < r_list * r_list {
+                pairs.push((j1, j2));
+            }
+        };
+        match boundaries
+            .box_dimensions()
+            .and_then(|dimensions| CellList::new(pos, dimensions, r_list))
+        {
+            Some(cells) => cells.for_each_pair(add),
+            None => for_each_pair(pos.len(), add),
+        }
+
+        self.partners = vec![Vec::new(); pos.len()];
+        for &(j1, j2) in self.pairs.iter() {
+            self.partners[j1].push(j2);
+            self.partners[j2].push(j1);
+        }
+
+        self.reference.clear();
+        for position in pos.iter() {
+            self.reference.extend_from_slice(position.components());
+        }
+        self.reference_offset = boundaries.shear_offset();
+        self.rebuilds += 1;
+    }
+
+    pub fn refresh<const D: usize>(
+        &mut self,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        r_cut: Real,
+    ) {
+        if self.needs_rebuild(pos, boundaries) {
+            self.rebuild(pos, boundaries, r_cut);
+        }
+    }
+
+    /// Rebuilds the list if needed and visits the stored pairs.
+    pub fn for_each_pair<const D: usize>(
+        &mut self,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+        r_cut: Real,
+        mut f: impl FnMut(usize, usize),
+    ) {
+        self.refresh(pos, boundaries, r_cut);
+        for &(j1, j2) in self.pairs.iter() {
+            f(j1, j2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, initial_state::cubic_lattice};
+
+    #[test]
+    fn rebuild_after_half_skin() {
+        let (region, mut pos) = cubic_lattice::<3>(125, 0.8);
+        let mut list = NeighbourList::new(0.4);
+        assert!(list.needs_rebuild(&pos, &region));
+        list.rebuild(&pos, &region, 2.5);
+        assert_eq!(1, list.rebuilds());
+        assert!(!list.needs_rebuild(&pos, &region));
+
+        pos[7] += DVector::from([0.15, 0., 0.]);
+        assert!(!list.needs_rebuild(&pos, &region));
+        pos[7] += DVector::from([0., 0.15, 0.]);
+        assert!(list.needs_rebuild(&pos, &region));
+
+        pos.pop();
+        list.rebuild(&pos, &region, 2.5);
+        assert!(!list.needs_rebuild(&pos, &region));
+        pos.pop();
+        assert!(list.needs_rebuild(&pos, &region));
+    }
+
+    #[test]
+    fn displacement_across_boundary() {
+        let region = Region::new([10.; 2]);
+        let mut pos = vec![DVector::from([4.95, 0.]), DVector::from([0., 0.])];
+        let mut list = NeighbourList::new(0.4);
+        list.rebuild(&pos, &region, 2.5);
+        pos[0] = DVector::from([-4.95, 0.]);
+        assert!(!list.needs_rebuild(&pos, &region));
+    }
+}
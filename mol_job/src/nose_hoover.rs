@@ -0,0 +1,209 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    integrator::{Integrator, StepError},
+    potential::PotentialEnergy,
+    prop::{degrees_of_freedom, mvv_sum},
+    state::MolecularState,
+    verlet,
+};
+use d_vector::{DVector, Real};
+use std::cell::RefCell;
+
+/// Position and velocity of one thermostat in the chain.
+#[derive(Debug, Default, Clone, Copy)]
+struct Link {
+    eta: Real,
+    v_eta: Real,
+}
+
+/// Nosé–Hoover chain thermostat for NVT runs.
+///
+/// The chain is advanced by half a step before and after the kick-drift-kick
+/// of `verlet::single_step` (the Martyna–Tuckerman–Klein splitting),
+/// so that friction only ever rescales velocities.
+#[derive(Debug)]
+pub struct NoseHoover {
+    temperature: Real,
+    tau: Real,
+    chain: RefCell<Vec<Link>>,
+}
+
+impl NoseHoover {
+    /// A single thermostat coupled with time constant `tau`.
+    pub fn new(temperature: Real, tau: Real) -> Self {
+        Self {
+            temperature,
+            tau,
+            chain: RefCell::new(vec![Link::default()]),
+        }
+    }
+
+    pub fn chain_length(self, length: usize) -> Self {
+        assert!(
+            length > 0,
+            "Nosé–Hoover chain needs at least one thermostat"
+        );
+        *self.chain.borrow_mut() = vec![Link::default(); length];
+        self
+    }
+
+    pub fn temperature(&self) -> Real {
+        self.temperature
+    }
+
+    /// Friction coefficient acting on the atoms.
+    pub fn friction(&self) -> Real {
+        self.chain.borrow()[0].v_eta
+    }
+
+    /// Energy of the extended system, which the dynamics conserve.
+    /// `potential_energy` must hold the forces of the current positions.
+    pub fn conserved_energy<const D: usize>(
+        &self,
+        state: &dyn MolecularState<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Real {
+        let kt = self.temperature;
+        let masses = state.atom_masses();
+        let dof = degrees_of_freedom(state);
+        let vel = state.get_vel();
+        let q = self.masses(dof);
+        let chain = self.chain.borrow();
+        let mut energy = 0.5 * mvv_sum(&vel, &masses) + potential_energy.u_sum();
+        for (k, (link, q)) in chain.iter().zip(q.iter()).enumerate() {
+            energy += 0.5 * q * link.v_eta * link.v_eta;
+            energy += if k == 0 {
+                dof * kt * link.eta
+            } else {
+                kt * link.eta
+            };
+        }
+        energy
+    }
+
+    fn masses(&self, dof: Real) -> Vec<Real> {
+        let q = self.temperature * self.tau * self.tau;
+        let mut masses = vec![q; self.chain.borrow().len()];
+        masses[0] *= dof;
+        masses
+    }
+
+    fn half_step_chain<const D: usize>(
+        &self,
+        delta_t: Real,
+        vel: &mut [DVector<D>],
+        masses: &[Real],
+        dof: Real,
+    ) {
+        let kt = self.temperature;
+        let q = self.masses(dof);
+        let mut chain = self.chain.borrow_mut();
+        let m = chain.len();
+        let dt2 = delta_t / 2.;
+        let dt4 = delta_t / 4.;
+        let dt8 = delta_t / 8.;
+        let mut ke2 = mvv_sum(vel, masses);
+
+        let force = |chain: &[Link], k: usize, ke2: Real| -> Real {
+            if k == 0 {
+                (ke2 - dof * kt) / q[0]
+            } else {
+                (q[k - 1] * chain[k - 1].v_eta * chain[k - 1].v_eta - kt) / q[k]
+            }
+        };
+        let kick = |chain: &mut [Link], k: usize, ke2: Real| {
+            if k + 1 < m {
+                chain[k].v_eta *= (-chain[k + 1].v_eta * dt8).exp();
+            }
+            chain[k].v_eta += force(chain, k, ke2) * dt4;
+            if k + 1 < m {
+                chain[k].v_eta *= (-chain[k + 1].v_eta * dt8).exp();
+            }
+        };
+
+        for k in (0..m).rev() {
+            kick(&mut chain, k, ke2);
+        }
+        for link in chain.iter_mut() {
+            link.eta += link.v_eta * dt2;
+        }
+        let scale = (-chain[0].v_eta * dt2).exp();
+        for velocity in vel.iter_mut() {
+            *velocity = scale * &*velocity;
+        }
+        ke2 *= scale * scale;
+        for k in 0..m {
+            kick(&mut chain, k, ke2);
+        }
+    }
+}
+
+impl<const D: usize> Integrator<D> for NoseHoover {
+    fn single_step(
+        &self,
+        delta_t: Real,
+        state: &dyn MolecularState<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        potential_energy: &dyn PotentialEnergy<D>,
+    ) -> Result<(), StepError> {
+        let masses = state.atom_masses();
+        let dof = degrees_of_freedom(state);
+        let mut vel = state.get_vel();
+        self.half_step_chain(delta_t, &mut vel, &masses, dof);
+        verlet::single_step(
+            delta_t,
+            &mut state.get_pos(),
+            &mut vel,
+            &mut state.get_acc(),
+            &mut state.get_images(),
+            &masses,
+            boundaries,
+            potential_energy,
+        );
+        self.half_step_chain(delta_t, &mut vel, &masses, dof);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        initial_state::{cubic_lattice, randomize_vectors},
+        lennard_jones::LennardJones,
+        state::State,
+    };
+
+    #[test]
+    fn reaches_target_temperature_and_conserves_energy() {
+        let (region, pos) = cubic_lattice::<3>(64, 0.6);
+        let n_mol = pos.len();
+        let state = State::default();
+        for position in pos {
+            state.insert_atom(position);
+        }
+        randomize_vectors(&mut state.get_vel(), 1.);
+        let wca = LennardJones::new((2 as Real).powf(1. / 6.));
+        wca.compute_forces(&state.get_pos(), &mut state.get_acc(), &region);
+
+        let thermostat = NoseHoover::new(2., 0.1).chain_length(3);
+        let delta_t = 0.002;
+        let initial = thermostat.conserved_energy(&state, &wca);
+        let mut temperature_sum = 0.;
+        let steps = 4000;
+        for step in 0..steps {
+            Integrator::single_step(&thermostat, delta_t, &state, &region, &wca).unwrap();
+            if step >= steps / 2 {
+                let vv_sum: Real = state.get_vel().iter().map(|v| v.square_length()).sum();
+                temperature_sum += vv_sum / degrees_of_freedom(&state);
+            }
+        }
+        let conserved = thermostat.conserved_energy(&state, &wca);
+        let temperature = temperature_sum / (steps / 2) as Real;
+
+        assert!((conserved - initial).abs() < 5e-3 * initial.abs());
+        assert!((temperature - 2.).abs() < 0.1, "T = {}", temperature);
+    }
+}
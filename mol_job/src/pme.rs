@@ -0,0 +1,462 @@
+#![allow(unused, dead_code)]
+
+use crate::{
+    boundaries::BoundaryConditions,
+    ewald::{add_dyad, erfc, Ewald},
+    potential::PotentialEnergy,
+    state::MolecularState,
+};
+use d_vector::{reset_array, DVector, Real};
+use rustfft::{num_complex::Complex, FftDirection, FftPlanner};
+
+const PI: Real = std::f64::consts::PI as Real;
+
+/// Smooth particle-mesh Ewald summation: the real-space, self and background
+/// terms of `Ewald`, but with charges spread on a grid by B-splines of `order`
+/// and the reciprocal sum done by FFT, which scales as N log N. The real-space
+/// sum goes over a cell list, as in `Ewald`, once the box is three `r_cut` wide.
+///
+/// `new` splits the sum so that the real-space part is cut at `r_cut` with
+/// relative error `tolerance` and picks a grid fine enough for the reciprocal
+/// part to match.
+#[derive(Debug)]
+pub struct Pme {
+    ewald: Ewald,
+    spacing: Real,
+    order: usize,
+}
+
+impl Pme {
+    pub fn new(r_cut: Real, tolerance: Real) -> Self {
+        assert!(
+            0. < tolerance && tolerance < 1.,
+            "Tolerance must be in (0, 1)"
+        );
+        // erfc(alpha r_cut) = tolerance by bisection, erfc decreasing.
+        let (mut low, mut high) = (0 as Real, 1 as Real);
+        while erfc(high * r_cut) > tolerance {
+            high *= 2.;
+        }
+        for _ in 0..50 {
+            let middle = (low + high) / 2.;
+            if erfc(middle * r_cut) > tolerance {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        let alpha = high;
+        // Wave numbers beyond this are damped by exp(-π² m² / alpha²) below tolerance.
+        let m_max = alpha * (-tolerance.ln()).sqrt() / PI;
+        Self {
+            ewald: Ewald::new(alpha, r_cut, 0),
+            spacing: 1. / (2. * m_max),
+            order: 6,
+        }
+    }
+
+    /// Order of the B-splines spreading the charges, 6 by default.
+    pub fn order(mut self, order: usize) -> Self {
+        assert!(order >= 3, "B-splines must be at least quadratic");
+        self.order = order;
+        self
+    }
+
+    /// Largest distance between grid points instead of the one `new` picks.
+    pub fn grid_spacing(mut self, spacing: Real) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn alpha(&self) -> Real {
+        self.ewald.alpha()
+    }
+
+    /// Number of grid points along each axis of a box with the given dimensions.
+    pub fn grid(&self, dimensions: &[Real; 3]) -> [usize; 3] {
+        dimensions.map(|length| ((length / self.spacing).ceil() as usize).max(self.order))
+    }
+
+    /// Spreads the `charges` on the grid, solves for the potential there by FFT
+    /// and interpolates it back; adds the forces to `acc`, the virial to `w_sum`.
+    fn reciprocal_sum(
+        &self,
+        pos: &[DVector<3>],
+        charges: &[Real],
+        acc: &mut [DVector<3>],
+        dimensions: &[Real; 3],
+        volume: Real,
+        w_sum: &mut [[Real; 3]; 3],
+    ) -> Real {
+        let grid = self.grid(dimensions);
+        let q = |j: usize| charges.get(j).copied().unwrap_or_default();
+        let splines: Vec<Spline> = pos
+            .iter()
+            .map(|position| Spline::new(position, dimensions, &grid, self.order))
+            .collect();
+
+        let mut mesh = vec![Complex::new(0 as Real, 0.); grid.iter().product()];
+        for (j, spline) in splines.iter().enumerate() {
+            let q_j = q(j);
+            if q_j != 0. {
+                spline.for_each_point(&grid, |index, weight, _| mesh[index].re += q_j * weight);
+            }
+        }
+        fft(&mut mesh, &grid, FftDirection::Forward);
+
+        let moduli = grid.map(|points| bspline_moduli(points, self.order));
+        let alpha = self.alpha();
+        let mut u_reciprocal = 0 as Real;
+        for (index, value) in mesh.iter_mut().enumerate() {
+            let n = [
+                index / (grid[1] * grid[2]),
+                index / grid[2] % grid[1],
+                index % grid[2],
+            ];
+            if n == [0; 3] {
+                *value = Complex::new(0., 0.);
+                continue;
+            }
+            let mut m = [0 as Real; 3];
+            for (a, m) in m.iter_mut().enumerate() {
+                let signed = if 2 * n[a] > grid[a] {
+                    n[a] as Real - grid[a] as Real
+                } else {
+                    n[a] as Real
+                };
+                *m = signed / dimensions[a];
+            }
+            let m = DVector::from(m);
+            let mm = m.square_length();
+            let modulus: Real = (0..3).map(|a| moduli[a][n[a]]).product();
+            let kernel = (-PI * PI * mm / (alpha * alpha)).exp() / (PI * volume * mm * modulus);
+            let u = 0.5 * kernel * value.norm_sqr();
+            u_reciprocal += u;
+            for (a, row) in w_sum.iter_mut().enumerate() {
+                row[a] += u;
+            }
+            add_dyad(
+                w_sum,
+                -2. * u * (1. + PI * PI * mm / (alpha * alpha)) / mm,
+                &m,
+            );
+            *value *= kernel;
+        }
+        fft(&mut mesh, &grid, FftDirection::Inverse);
+
+        for (j, (spline, acceleration)) in splines.iter().zip(acc.iter_mut()).enumerate() {
+            let q_j = q(j);
+            if q_j == 0. {
+                continue;
+            }
+            let mut gradient = [0 as Real; 3];
+            spline.for_each_point(&grid, |index, _, slope| {
+                for (g, s) in gradient.iter_mut().zip(slope) {
+                    *g += s * mesh[index].re;
+                }
+            });
+            for (a, g) in gradient.iter_mut().enumerate() {
+                *g *= -q_j * grid[a] as Real / dimensions[a];
+            }
+            *acceleration += &DVector::from(gradient);
+        }
+        u_reciprocal
+    }
+}
+
+impl PotentialEnergy<3> for Pme {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<3>],
+        acc: &mut [DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) {
+        assert_eq!(pos.len(), acc.len());
+        let dimensions = self.ewald.dimensions(boundaries);
+        let volume = boundaries.volume();
+        let mut w_sum = [[0 as Real; 3]; 3];
+        let u_real = self.ewald.real_space_sum(pos, acc, boundaries, &mut w_sum);
+        let u_fixed = self
+            .ewald
+            .self_and_background(pos.len(), volume, &mut w_sum);
+        let u_reciprocal = self.reciprocal_sum(
+            pos,
+            &self.ewald.charges(),
+            acc,
+            &dimensions,
+            volume,
+            &mut w_sum,
+        );
+        self.ewald.store(u_real + u_reciprocal + u_fixed, w_sum);
+    }
+
+    fn attach(&self, state: &dyn MolecularState<3>) {
+        self.ewald.attach(state);
+    }
+
+    fn min_box_length(&self) -> Real {
+        2. * self.ewald.r_cut()
+    }
+
+    /// Real-space interaction of the charge of `atom` with the others, as in
+    /// `Ewald`, and its reciprocal part: the difference of two mesh sums with
+    /// and without its charge, which keeps every other atom in place.
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<3>,
+        pos: &[DVector<3>],
+        boundaries: &dyn BoundaryConditions<3>,
+    ) -> Real {
+        let dimensions = self.ewald.dimensions(boundaries);
+        let volume = boundaries.volume();
+        let mut charges = self.ewald.charges().clone();
+        let q_atom = charges.get(atom).copied().unwrap_or_default();
+        if q_atom == 0. {
+            return 0.;
+        }
+        let mut moved = pos.to_vec();
+        if atom < moved.len() {
+            moved[atom] = position.clone();
+        } else {
+            moved.push(position.clone());
+        }
+        charges.resize(moved.len(), 0.);
+        let mut acc = vec![DVector::default(); moved.len()];
+        let mut w_sum = [[0 as Real; 3]; 3];
+        charges[atom] = q_atom;
+        let with = self.reciprocal_sum(&moved, &charges, &mut acc, &dimensions, volume, &mut w_sum);
+        charges[atom] = 0.;
+        let without =
+            self.reciprocal_sum(&moved, &charges, &mut acc, &dimensions, volume, &mut w_sum);
+        self.ewald.real_space_atom(atom, position, pos, boundaries) + with - without
+            + self.ewald.self_and_background_atom(atom, pos, volume)
+    }
+
+    fn u_sum(&self) -> Real {
+        self.ewald.u_sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.ewald.virial_sum()
+    }
+
+    fn virial_tensor(&self) -> [[Real; 3]; 3] {
+        self.ewald.virial_tensor()
+    }
+}
+
+/// B-spline weights of one atom on the grid points around it along every axis:
+/// point `base - j` gets `theta[j]`, whose derivative in grid units is `slope[j]`.
+struct Spline {
+    base: [isize; 3],
+    theta: [Vec<Real>; 3],
+    slope: [Vec<Real>; 3],
+}
+
+impl Spline {
+    fn new(position: &DVector<3>, dimensions: &[Real; 3], grid: &[usize; 3], order: usize) -> Self {
+        let mut base = [0; 3];
+        let mut theta: [Vec<Real>; 3] = Default::default();
+        let mut slope: [Vec<Real>; 3] = Default::default();
+        for a in 0..3 {
+            let u = grid[a] as Real * (position.components()[a] / dimensions[a] + 0.5);
+            let floor = u.floor();
+            base[a] = floor as isize;
+            (theta[a], slope[a]) = bspline(u - floor, order);
+        }
+        Self { base, theta, slope }
+    }
+
+    /// Calls `f` with the index of every grid point the atom is spread on,
+    /// its weight and the gradient of the weight.
+    fn for_each_point(&self, grid: &[usize; 3], mut f: impl FnMut(usize, Real, [Real; 3])) {
+        let point =
+            |a: usize, j: usize| (self.base[a] - j as isize).rem_euclid(grid[a] as isize) as usize;
+        let order = self.theta[0].len();
+        for j0 in 0..order {
+            let i0 = point(0, j0) * grid[1];
+            for j1 in 0..order {
+                let i1 = (i0 + point(1, j1)) * grid[2];
+                let w01 = self.theta[0][j0] * self.theta[1][j1];
+                for j2 in 0..order {
+                    let [t0, t1, t2] = [self.theta[0][j0], self.theta[1][j1], self.theta[2][j2]];
+                    let [s0, s1, s2] = [self.slope[0][j0], self.slope[1][j1], self.slope[2][j2]];
+                    f(
+                        i1 + point(2, j2),
+                        w01 * t2,
+                        [s0 * t1 * t2, t0 * s1 * t2, t0 * t1 * s2],
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Cardinal B-spline of `order` at `w + j` for `j` in `0..order`, with its derivatives.
+fn bspline(w: Real, order: usize) -> (Vec<Real>, Vec<Real>) {
+    let mut theta = vec![0 as Real; order];
+    theta[0] = w;
+    theta[1] = 1. - w;
+    let mut slope = vec![0 as Real; order];
+    for n in 3..=order {
+        if n == order {
+            for j in 0..order {
+                slope[j] = theta[j] - if j > 0 { theta[j - 1] } else { 0. };
+            }
+        }
+        let previous = theta.clone();
+        for j in 0..n {
+            let x = w + j as Real;
+            let lower = if j > 0 { previous[j - 1] } else { 0. };
+            theta[j] = (x * previous[j] + (n as Real - x) * lower) / (n - 1) as Real;
+        }
+    }
+    (theta, slope)
+}
+
+/// Squared moduli of the Fourier transform of the B-spline on `points` grid points.
+fn bspline_moduli(points: usize, order: usize) -> Vec<Real> {
+    let (values, _) = bspline(0., order);
+    let mut moduli: Vec<Real> = (0..points)
+        .map(|m| {
+            let (mut re, mut im) = (0 as Real, 0 as Real);
+            for (k, value) in values[1..].iter().enumerate() {
+                let phase = 2. * PI * (m * k) as Real / points as Real;
+                re += value * phase.cos();
+                im += value * phase.sin();
+            }
+            re * re + im * im
+        })
+        .collect();
+    // Odd orders vanish at the Nyquist frequency: borrow from the neighbours.
+    for m in 0..points {
+        if moduli[m] < 1e-7 {
+            moduli[m] = (moduli[(m + points - 1) % points] + moduli[(m + 1) % points]) / 2.;
+        }
+    }
+    moduli
+}
+
+/// Unnormalised 3D FFT of a grid stored row by row.
+fn fft(mesh: &mut [Complex<Real>], grid: &[usize; 3], direction: FftDirection) {
+    let mut planner = FftPlanner::<Real>::new();
+    let strides = [grid[1] * grid[2], grid[2], 1];
+    for a in 0..3 {
+        let transform = planner.plan_fft(grid[a], direction);
+        let mut line = vec![Complex::new(0 as Real, 0.); grid[a]];
+        for start in 0..mesh.len() {
+            if !(start / strides[a]).is_multiple_of(grid[a]) {
+                continue;
+            }
+            for (i, c) in line.iter_mut().enumerate() {
+                *c = mesh[start + i * strides[a]];
+            }
+            transform.process(&mut line);
+            for (i, c) in line.iter().enumerate() {
+                mesh[start + i * strides[a]] = *c;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, state::State};
+
+    /// Rock salt with every ion nudged off its site.
+    fn molten_salt() -> (Region<3>, State<3>) {
+        let state = State::default();
+        let n = 4;
+        for i in 0..n * n * n {
+            let c = [i / (n * n), i / n % n, i % n];
+            let mut x = c.map(|c| c as Real - n as Real / 2.);
+            x[0] += 0.2 * (i as Real * 0.7).sin();
+            x[1] += 0.2 * (i as Real * 1.3).cos();
+            x[2] += 0.2 * (i as Real * 2.9).sin();
+            state.insert_atom(DVector::from(x));
+            state.get_charges()[i] = if c.iter().sum::<usize>() % 2 == 0 {
+                1.
+            } else {
+                -1.
+            };
+        }
+        (Region::new([n as Real; 3]), state)
+    }
+
+    #[test]
+    fn bsplines_sum_to_one() {
+        for order in 3..8 {
+            let (theta, slope) = bspline(0.3, order);
+            assert!((theta.iter().sum::<Real>() - 1.).abs() < 1e-6);
+            assert!(slope.iter().sum::<Real>().abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn matches_direct_ewald() {
+        let (region, state) = molten_salt();
+        let pme = Pme::new(2., 1e-5);
+        let ewald = Ewald::new(pme.alpha(), 2., 12);
+        let n_mol = state.get_pos().len();
+        let (mut acc_pme, mut acc_ewald) = (
+            vec![DVector::default(); n_mol],
+            vec![DVector::default(); n_mol],
+        );
+        pme.attach(&state);
+        ewald.attach(&state);
+        pme.compute_forces(&state.get_pos(), &mut acc_pme, &region);
+        ewald.compute_forces(&state.get_pos(), &mut acc_ewald, &region);
+
+        let u = ewald.u_sum();
+        assert!(
+            (pme.u_sum() - u).abs() < 1e-4 * u.abs(),
+            "{} vs {}",
+            pme.u_sum(),
+            u
+        );
+        assert!((pme.virial_sum() - ewald.virial_sum()).abs() < 1e-3 * u.abs());
+        let scale: Real = acc_ewald.iter().map(|a| a.length()).sum::<Real>() / n_mol as Real;
+        for (a, b) in acc_pme.iter().zip(acc_ewald.iter()) {
+            assert!((a - b).length() < 1e-3 * scale, "{:?} vs {:?}", a, b);
+        }
+
+        let coarse = Pme::new(2., 1e-5).grid_spacing(0.5).order(4);
+        coarse.attach(&state);
+        coarse.compute_forces(&state.get_pos(), &mut acc_pme, &region);
+        assert!((coarse.u_sum() - u).abs() > (pme.u_sum() - u).abs());
+    }
+
+    #[test]
+    fn atom_energy_matches_direct_ewald() {
+        let (region, state) = molten_salt();
+        let pme = Pme::new(2., 1e-5);
+        let ewald = Ewald::new(pme.alpha(), 2., 12);
+        pme.attach(&state);
+        ewald.attach(&state);
+        let pos = state.get_pos().clone();
+        for atom in [0, 5, 62, pos.len() - 1] {
+            let moved = &pos[atom] + &DVector::from([0.2, 0.1, -0.3]);
+            let u = ewald.atom_energy(atom, &moved, &pos, &region);
+            let u_pme = pme.atom_energy(atom, &moved, &pos, &region);
+            assert!(
+                (u_pme - u).abs() < 1e-3 * u.abs(),
+                "{}: {} vs {}",
+                atom,
+                u_pme,
+                u
+            );
+        }
+    }
+}
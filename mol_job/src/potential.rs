@@ -1,22 +1,87 @@
 #![allow(unused, dead_code)]
 
-use crate::boundaries::BoundaryConditions;
-use d_vector::{DVector, Real};
+use crate::{boundaries::BoundaryConditions, state::MolecularState};
+use d_vector::{reset_array, DVector, Real};
 use std::{cell::Cell, fmt::Debug};
 
-pub trait PotentialEnergy<const D: usize>: Debug {
+#[cfg(not(feature = "f64"))]
+pub(crate) use atomic_float::AtomicF32 as AtomicReal;
+#[cfg(feature = "f64")]
+pub(crate) use atomic_float::AtomicF64 as AtomicReal;
+
+/// Implementations are shared between threads when forces are computed in parallel.
+pub trait PotentialEnergy<const D: usize>: Debug + Sync {
+    /// Writes the force on every atom to `acc`; integrators divide them by the
+    /// masses in the state, see `verlet::compute_accelerations`.
     fn compute_forces(
         &self,
         pos: &[DVector<D>],
         acc: &mut [DVector<D>],
         boundaries: &dyn BoundaryConditions<D>,
     );
+    /// Adds the forces to whatever `acc` already holds, which lets several
+    /// potentials act on the same atoms. The default goes through a scratch array.
+    fn add_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        let mut own = vec![DVector::default(); acc.len()];
+        self.compute_forces(pos, &mut own, boundaries);
+        for (a, f) in acc.iter_mut().zip(own.iter()) {
+            *a += f;
+        }
+    }
+    /// Takes whatever it needs to know about the atoms, such as their species,
+    /// from the state. Called before forces are computed and whenever atoms
+    /// have been added or removed since.
+    fn attach(&self, state: &dyn MolecularState<D>) {}
     fn u_sum(&self) -> Real {
         0.0
     }
     fn virial_sum(&self) -> Real {
         0.0
     }
+    /// Sum of `dr_a * f_b` over interacting pairs, `dr` being the separation
+    /// and `f` the force between them; its trace is `virial_sum`.
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        [[0.0; D]; D]
+    }
+    /// Energy of every term of a potential made of several, in term order.
+    fn u_terms(&self) -> Vec<Real> {
+        vec![self.u_sum()]
+    }
+    /// Virial of every term of a potential made of several, in term order.
+    fn virial_terms(&self) -> Vec<Real> {
+        vec![self.virial_sum()]
+    }
+    /// Energy and virial left out by the cutoff for `n_mol` atoms in `volume`,
+    /// assuming the fluid beyond the cutoff is uniform. `ThermoProps` adds them
+    /// to what it reports; they are zero unless the potential opts in.
+    fn tail_correction(&self, n_mol: usize, volume: Real) -> (Real, Real) {
+        (0.0, 0.0)
+    }
+    /// Energy of `atom` placed at `position` with all the other atoms of `pos`,
+    /// which is what Monte Carlo moves need. `atom` equal to `pos.len()` stands
+    /// for an atom about to be inserted. Leaves `u_sum` and the virials alone.
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real;
+    /// How many times a neighbour list has been rebuilt, if the potential keeps one.
+    fn neighbour_list_rebuilds(&self) -> Option<usize> {
+        None
+    }
+    /// Shortest box length the potential works in, such as twice a cutoff
+    /// it takes with the minimum image. `Job::run` stops when a barostat
+    /// shrinks the box below it.
+    fn min_box_length(&self) -> Real {
+        0.0
+    }
 }
 
 #[derive(Debug, Default)]
@@ -30,4 +95,166 @@ impl<const D: usize> PotentialEnergy<D> for NoInteraction {
     ) {
     }
 
+    fn atom_energy(
+        &self,
+        _: usize,
+        _: &DVector<D>,
+        _: &[DVector<D>],
+        _: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        0.0
+    }
+}
+
+/// Several potentials acting together, e.g. Lennard-Jones between charged atoms
+/// plus `Ewald` or `Pme`, each added with `term`. Energy and virial are totals
+/// over the terms; `u_terms` and `virial_terms` break them down in term order.
+#[derive(Debug)]
+pub struct Composite<const D: usize> {
+    terms: Vec<Box<dyn PotentialEnergy<D>>>,
+}
+
+impl<const D: usize> Default for Composite<D> {
+    fn default() -> Self {
+        Self { terms: vec![] }
+    }
+}
+
+impl<const D: usize> Composite<D> {
+    pub fn term(mut self, potential: impl PotentialEnergy<D> + 'static) -> Self {
+        self.terms.push(Box::new(potential));
+        self
+    }
+
+    pub fn terms(&self) -> &[Box<dyn PotentialEnergy<D>>] {
+        &self.terms
+    }
+}
+
+impl<const D: usize> PotentialEnergy<D> for Composite<D> {
+    fn compute_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        reset_array(acc);
+        self.add_forces(pos, acc, boundaries);
+    }
+
+    fn add_forces(
+        &self,
+        pos: &[DVector<D>],
+        acc: &mut [DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) {
+        for term in self.terms.iter() {
+            term.add_forces(pos, acc, boundaries);
+        }
+    }
+
+    fn attach(&self, state: &dyn MolecularState<D>) {
+        for term in self.terms.iter() {
+            term.attach(state);
+        }
+    }
+
+    fn u_sum(&self) -> Real {
+        self.terms.iter().map(|term| term.u_sum()).sum()
+    }
+
+    fn virial_sum(&self) -> Real {
+        self.terms.iter().map(|term| term.virial_sum()).sum()
+    }
+
+    fn u_terms(&self) -> Vec<Real> {
+        self.terms.iter().map(|term| term.u_sum()).collect()
+    }
+
+    fn virial_terms(&self) -> Vec<Real> {
+        self.terms.iter().map(|term| term.virial_sum()).collect()
+    }
+
+    fn virial_tensor(&self) -> [[Real; D]; D] {
+        let mut tensor = [[0.0; D]; D];
+        for term in self.terms.iter() {
+            for (row, other) in tensor.iter_mut().zip(term.virial_tensor()) {
+                for (w, o) in row.iter_mut().zip(other) {
+                    *w += o;
+                }
+            }
+        }
+        tensor
+    }
+
+    fn tail_correction(&self, n_mol: usize, volume: Real) -> (Real, Real) {
+        self.terms.iter().fold((0.0, 0.0), |(u, w), term| {
+            let (du, dw) = term.tail_correction(n_mol, volume);
+            (u + du, w + dw)
+        })
+    }
+
+    fn atom_energy(
+        &self,
+        atom: usize,
+        position: &DVector<D>,
+        pos: &[DVector<D>],
+        boundaries: &dyn BoundaryConditions<D>,
+    ) -> Real {
+        self.terms
+            .iter()
+            .map(|term| term.atom_energy(atom, position, pos, boundaries))
+            .sum()
+    }
+
+    fn neighbour_list_rebuilds(&self) -> Option<usize> {
+        self.terms
+            .iter()
+            .find_map(|term| term.neighbour_list_rebuilds())
+    }
+
+    fn min_box_length(&self) -> Real {
+        self.terms
+            .iter()
+            .map(|term| term.min_box_length())
+            .fold(0.0, Real::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, initial_state::cubic_lattice, lennard_jones::LennardJones};
+
+    #[test]
+    fn composite_adds_up_its_terms() {
+        let (region, pos) = cubic_lattice::<3>(64, 0.9);
+        let pos: Vec<DVector<3>> = pos
+            .iter()
+            .enumerate()
+            .map(|(j, r)| r + &DVector::from([0.05 * (j as Real).sin(), 0., 0.]))
+            .collect();
+        let (near, far): (&dyn PotentialEnergy<3>, &dyn PotentialEnergy<3>) =
+            (&LennardJones::new(1.5), &LennardJones::new(2.));
+        let mut acc_near = vec![DVector::default(); pos.len()];
+        let mut acc_far = vec![DVector::default(); pos.len()];
+        near.compute_forces(&pos, &mut acc_near, &region);
+        far.compute_forces(&pos, &mut acc_far, &region);
+
+        let both = Composite::default()
+            .term(LennardJones::new(1.5))
+            .term(LennardJones::new(2.));
+        let mut acc = vec![DVector::from([1., 1., 1.]); pos.len()];
+        both.compute_forces(&pos, &mut acc, &region);
+        let u = [near.u_sum(), far.u_sum()];
+        assert_eq!(u.to_vec(), both.u_terms());
+        assert_eq!(
+            vec![near.virial_sum(), far.virial_sum()],
+            both.virial_terms()
+        );
+        assert!((both.u_sum() - u[0] - u[1]).abs() < 1e-4);
+        for (a, (n, f)) in acc.iter().zip(acc_near.iter().zip(acc_far.iter())) {
+            assert!((a - &(n + f)).length() < 1e-4);
+        }
+    }
 }
@@ -1,12 +1,20 @@
 #![allow(unused, dead_code)]
 
-use crate::potential::PotentialEnergy;
-use d_vector::DVector;
-use std::fmt::Debug;
+use crate::{
+    barostat::instant_pressure, boundaries::BoundaryConditions, potential::PotentialEnergy,
+    state::MolecularState,
+};
+use d_vector::{DVector, Real};
+use std::{cell::Cell, fmt::Debug};
 
 pub trait Props<const D: usize>: Debug {
     fn reset(&self);
-    fn eval_props(&self, u: &dyn PotentialEnergy<D>, pos: &[DVector<D>], vel: &[DVector<D>]);
+    fn eval_props(
+        &self,
+        u: &dyn PotentialEnergy<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        state: &dyn MolecularState<D>,
+    );
     fn accum_props(&self);
     fn need_avg(&self, step_count: usize) -> bool {
         true
@@ -21,9 +29,266 @@ pub struct TrivialProps<const D: usize>;
 impl<const D: usize> Props<D> for TrivialProps<D> {
     fn reset(&self) {}
 
-    fn eval_props(&self, u: &dyn PotentialEnergy<D>, pos: &[DVector<D>], vel: &[DVector<D>]) {}
+    fn eval_props(
+        &self,
+        u: &dyn PotentialEnergy<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        state: &dyn MolecularState<D>,
+    ) {
+    }
 
     fn accum_props(&self) {}
 
     fn avg_props(&self) {}
 }
+
+/// Mean and standard deviation of a property over an averaging interval.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Average {
+    pub mean: Real,
+    pub std_dev: Real,
+}
+
+/// Current value of a property together with its running sums.
+#[derive(Debug, Default, Clone, Copy)]
+struct Prop {
+    val: Real,
+    sum: Real,
+    sum2: Real,
+}
+
+impl Prop {
+    fn accum(&mut self) {
+        self.sum += self.val;
+        self.sum2 += self.val * self.val;
+    }
+
+    fn avg(&self, n: usize) -> Average {
+        let mean = self.sum / n as Real;
+        let variance = self.sum2 / n as Real - mean * mean;
+        Average {
+            mean,
+            std_dev: variance.max(0.).sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ThermoSummary {
+    pub steps: usize,
+    pub kin_energy: Average,
+    pub tot_energy: Average,
+    pub temperature: Average,
+    pub pressure: Average,
+}
+
+/// Kinetic and total energy per atom, temperature and pressure
+/// averaged over every `step_avg` steps, read back through `summary`.
+/// Masses come from the state; the Boltzmann constant is 1.
+/// Energy and pressure include the tail correction of the potential.
+#[derive(Debug)]
+pub struct ThermoProps<const D: usize> {
+    step_avg: usize,
+    count: Cell<usize>,
+    kin_energy: Cell<Prop>,
+    tot_energy: Cell<Prop>,
+    temperature: Cell<Prop>,
+    pressure: Cell<Prop>,
+    summary: Cell<Option<ThermoSummary>>,
+}
+
+impl<const D: usize> Default for ThermoProps<D> {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl<const D: usize> ThermoProps<D> {
+    pub fn new(step_avg: usize) -> Self {
+        Self {
+            step_avg,
+            count: Cell::new(0),
+            kin_energy: Cell::default(),
+            tot_energy: Cell::default(),
+            temperature: Cell::default(),
+            pressure: Cell::default(),
+            summary: Cell::new(None),
+        }
+    }
+
+    /// Averages of the last completed interval.
+    pub fn summary(&self) -> Option<ThermoSummary> {
+        self.summary.get()
+    }
+
+    fn props(&self) -> [&Cell<Prop>; 4] {
+        [
+            &self.kin_energy,
+            &self.tot_energy,
+            &self.temperature,
+            &self.pressure,
+        ]
+    }
+}
+
+fn set_val(prop: &Cell<Prop>, val: Real) {
+    prop.set(Prop { val, ..prop.get() });
+}
+
+impl<const D: usize> Props<D> for ThermoProps<D> {
+    fn reset(&self) {
+        self.count.set(0);
+        for prop in self.props() {
+            prop.set(Prop::default());
+        }
+    }
+
+    fn eval_props(
+        &self,
+        u: &dyn PotentialEnergy<D>,
+        boundaries: &dyn BoundaryConditions<D>,
+        state: &dyn MolecularState<D>,
+    ) {
+        let masses = state.atom_masses();
+        let dof = degrees_of_freedom(state);
+        let vel = state.get_vel();
+        let n_mol = vel.len();
+        if n_mol == 0 {
+            return;
+        }
+        let mvv_sum = mvv_sum(&vel, &masses);
+        let kin_energy = 0.5 * mvv_sum / n_mol as Real;
+        set_val(&self.kin_energy, kin_energy);
+        let (u_tail, virial_tail) = u.tail_correction(n_mol, boundaries.volume());
+        set_val(
+            &self.tot_energy,
+            kin_energy + (u.u_sum() + u_tail) / n_mol as Real,
+        );
+        set_val(&self.temperature, mvv_sum / dof);
+        set_val(
+            &self.pressure,
+            instant_pressure(&vel, &masses, u, boundaries.volume())
+                + virial_tail / (D as Real * boundaries.volume()),
+        );
+    }
+
+    fn accum_props(&self) {
+        self.count.set(self.count.get() + 1);
+        for prop in self.props() {
+            let mut p = prop.get();
+            p.accum();
+            prop.set(p);
+        }
+    }
+
+    fn need_avg(&self, step_count: usize) -> bool {
+        step_count.is_multiple_of(self.step_avg)
+    }
+
+    fn avg_props(&self) {
+        let n = self.count.get();
+        if n == 0 {
+            return;
+        }
+        self.summary.set(Some(ThermoSummary {
+            steps: n,
+            kin_energy: self.kin_energy.get().avg(n),
+            tot_energy: self.tot_energy.get().avg(n),
+            temperature: self.temperature.get().avg(n),
+            pressure: self.pressure.get().avg(n),
+        }));
+    }
+}
+
+/// Degrees of freedom of the atoms of `state` with their total momentum and
+/// the constraints of the topology fixed, which temperatures and thermostats
+/// divide the kinetic energy by. Borrows the positions, so call it before
+/// taking them.
+pub(crate) fn degrees_of_freedom<const D: usize>(state: &dyn MolecularState<D>) -> Real {
+    let n_mol = state.get_pos().len();
+    let n_constraints = state.get_topology().constraints.len();
+    (D * n_mol.saturating_sub(1).max(1))
+        .saturating_sub(n_constraints)
+        .max(1) as Real
+}
+
+/// Twice the kinetic energy, `Σ m v²`.
+pub(crate) fn mvv_sum<const D: usize>(vel: &[DVector<D>], masses: &[Real]) -> Real {
+    vel.iter()
+        .zip(masses)
+        .map(|(v, m)| m * v.square_length())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundaries::Region, potential::NoInteraction, state::State};
+
+    #[test]
+    fn ideal_gas() {
+        let props = ThermoProps::<2>::new(2);
+        let region = Region::new([2., 5.]);
+        let state = State::default();
+        *state.get_pos() = vec![DVector::default(); 3];
+        *state.get_vel() = vec![
+            DVector::from([1., 0.]),
+            DVector::from([0., -1.]),
+            DVector::from([-1., 1.]),
+        ];
+
+        props.eval_props(&NoInteraction, &region, &state);
+        props.accum_props();
+        assert!(!props.need_avg(1));
+        state.get_vel()[2] = DVector::from([-1., 0.]);
+        props.eval_props(&NoInteraction, &region, &state);
+        props.accum_props();
+        assert!(props.need_avg(2));
+        props.avg_props();
+        props.reset();
+
+        let close = |a: Real, b: Real| (a - b).abs() < 1e-6;
+        let s = props.summary().unwrap();
+        assert_eq!(2, s.steps);
+        assert!(close(7. / 12., s.kin_energy.mean));
+        assert!(close(1. / 12., s.kin_energy.std_dev));
+        assert_eq!(s.kin_energy, s.tot_energy);
+        assert!(close(0.875, s.temperature.mean));
+        assert!(close(0.175, s.pressure.mean));
+        assert!(close(0.025, s.pressure.std_dev));
+    }
+
+    #[test]
+    fn tail_correction_is_reported() {
+        use crate::lennard_jones::LennardJones;
+
+        let props = ThermoProps::<3>::new(1);
+        let region = Region::new([5.; 3]);
+        let state = State::default();
+        for _ in 0..10 {
+            state.insert_atom(DVector::default());
+        }
+        let potential = LennardJones::default().tail_corrected();
+        let (u_tail, virial_tail) = PotentialEnergy::<3>::tail_correction(&potential, 10, 125.);
+        props.eval_props(&potential, &region, &state);
+        props.accum_props();
+        props.avg_props();
+
+        let s = props.summary().unwrap();
+        assert!((s.tot_energy.mean - u_tail / 10.).abs() < 1e-6);
+        assert!((s.pressure.mean - virial_tail / 375.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn constraints_remove_degrees_of_freedom() {
+        use crate::topology::Topology;
+
+        let state = State::default();
+        *state.get_pos() = vec![DVector::<3>::default(); 4];
+        assert_eq!(9., degrees_of_freedom(&state));
+        *state.get_topology() = Topology::default()
+            .constraint(0, 1, 1.)
+            .constraint(2, 3, 1.);
+        assert_eq!(7., degrees_of_freedom(&state));
+    }
+}
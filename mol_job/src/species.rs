@@ -0,0 +1,144 @@
+#![allow(unused, dead_code)]
+
+use d_vector::Real;
+
+/// One kind of atom: its Lennard-Jones parameters with its own kind. Masses
+/// are kept by the state, next to the species of every atom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Species {
+    pub sigma: Real,
+    pub epsilon: Real,
+}
+
+impl Default for Species {
+    fn default() -> Self {
+        Self {
+            sigma: 1.,
+            epsilon: 1.,
+        }
+    }
+}
+
+/// Lennard-Jones parameters between two species.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairParameters {
+    pub sigma: Real,
+    pub epsilon: Real,
+    pub r_cut: Real,
+}
+
+/// Species of a system and the parameters of every pair of them,
+/// indexed by the species numbers stored in the state.
+#[derive(Debug, Clone)]
+pub struct Mixture {
+    species: Vec<Species>,
+    pairs: Vec<PairParameters>,
+}
+
+impl Default for Mixture {
+    /// A single species with unit σ and ε, cut at 2.5.
+    fn default() -> Self {
+        Self::lorentz_berthelot(vec![Species::default()], 2.5)
+    }
+}
+
+impl Mixture {
+    /// Lorentz–Berthelot mixing: σ of a pair is the arithmetic mean of the two σ,
+    /// ε the geometric mean of the two ε. Every pair is cut at `r_cut` times its σ.
+    pub fn lorentz_berthelot(species: Vec<Species>, r_cut: Real) -> Self {
+        assert!(!species.is_empty(), "A mixture needs at least one species");
+        let mut pairs = Vec::with_capacity(species.len() * species.len());
+        for a in species.iter() {
+            for b in species.iter() {
+                let sigma = (a.sigma + b.sigma) / 2.;
+                pairs.push(PairParameters {
+                    sigma,
+                    epsilon: (a.epsilon * b.epsilon).sqrt(),
+                    r_cut: r_cut * sigma,
+                });
+            }
+        }
+        Self { species, pairs }
+    }
+
+    /// Replaces the mixed parameters of the pair of species `a` and `b`,
+    /// e.g. for mixtures that do not follow any mixing rule.
+    pub fn pair(mut self, a: usize, b: usize, parameters: PairParameters) -> Self {
+        let n = self.n_species();
+        self.pairs[a * n + b] = parameters;
+        self.pairs[b * n + a] = parameters;
+        self
+    }
+
+    /// Kob–Andersen binary glass former: species 0 is the majority A,
+    /// species 1 the minority B, usually mixed 80:20. Cutoffs are at 2.5 σ.
+    pub fn kob_andersen() -> Self {
+        let b = Species {
+            sigma: 0.88,
+            epsilon: 0.5,
+        };
+        Self::lorentz_berthelot(vec![Species::default(), b], 2.5).pair(
+            0,
+            1,
+            PairParameters {
+                sigma: 0.8,
+                epsilon: 1.5,
+                r_cut: 2.,
+            },
+        )
+    }
+
+    pub fn n_species(&self) -> usize {
+        self.species.len()
+    }
+
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    pub fn pair_parameters(&self, a: usize, b: usize) -> &PairParameters {
+        &self.pairs[a * self.n_species() + b]
+    }
+
+    /// Largest cutoff of all pairs, which pair searches have to cover.
+    pub fn max_r_cut(&self) -> Real {
+        self.pairs.iter().map(|p| p.r_cut).fold(0., Real::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lorentz_berthelot_mixing() {
+        let mixture = Mixture::lorentz_berthelot(
+            vec![
+                Species::default(),
+                Species {
+                    sigma: 2.,
+                    epsilon: 4.,
+                },
+            ],
+            3.,
+        );
+        assert_eq!(
+            &PairParameters {
+                sigma: 1.5,
+                epsilon: 2.,
+                r_cut: 4.5,
+            },
+            mixture.pair_parameters(1, 0)
+        );
+        assert_eq!(mixture.pair_parameters(0, 1), mixture.pair_parameters(1, 0));
+        assert_eq!(6., mixture.max_r_cut());
+    }
+
+    #[test]
+    fn kob_andersen_cross_pair() {
+        let mixture = Mixture::kob_andersen();
+        assert_eq!(1.5, mixture.pair_parameters(1, 0).epsilon);
+        assert_eq!(0.88, mixture.pair_parameters(1, 1).sigma);
+        assert_eq!(2.5, mixture.max_r_cut());
+    }
+}
@@ -1,4 +1,5 @@
 #![allow(unused, dead_code)]
+use crate::topology::Topology;
 use d_vector::{DVector, Real};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -7,10 +8,55 @@ use std::{
 };
 
 pub trait MolecularState<const D: usize>: Debug {
-    fn get_pos(&self) -> RefMut<Vec<DVector<D>>>;
-    fn get_vel(&self) -> RefMut<Vec<DVector<D>>>;
-    fn get_acc(&self) -> RefMut<Vec<DVector<D>>>;
+    fn get_pos(&self) -> RefMut<'_, Vec<DVector<D>>>;
+    fn get_vel(&self) -> RefMut<'_, Vec<DVector<D>>>;
+    fn get_acc(&self) -> RefMut<'_, Vec<DVector<D>>>;
+    /// Boxes crossed by every atom along every axis since the start.
+    fn get_images(&self) -> RefMut<'_, Vec<[i32; D]>>;
+    /// Species of every atom, indexing the `Mixture` of the potential.
+    fn get_species(&self) -> RefMut<'_, Vec<usize>>;
+    /// Mass of every species; species left out weigh 1.
+    fn get_masses(&self) -> RefMut<'_, Vec<Real>>;
+    /// Electric charge of every atom.
+    fn get_charges(&self) -> RefMut<'_, Vec<Real>>;
+    /// Bonds, angles and dihedrals between the atoms.
+    fn get_topology(&self) -> RefMut<'_, Topology>;
     fn sync(&self, time_now: Real) {}
+    /// Mass of every atom, looked up by its species. Borrows the positions,
+    /// so call it before taking them.
+    fn atom_masses(&self) -> Vec<Real> {
+        let n_mol = self.get_pos().len();
+        let species = self.get_species();
+        let masses = self.get_masses();
+        (0..n_mol)
+            .map(|j| {
+                let s = species.get(j).copied().unwrap_or_default();
+                masses.get(s).copied().unwrap_or(1.)
+            })
+            .collect()
+    }
+    /// Appends an uncharged atom of species 0 at rest.
+    fn insert_atom(&self, position: DVector<D>) {
+        self.get_pos().push(position);
+        self.get_vel().push(DVector::default());
+        self.get_acc().push(DVector::default());
+        self.get_images().push([0; D]);
+        self.get_species().push(0);
+        self.get_charges().push(0.);
+    }
+    /// Removes an atom; the last atom takes its index, so there must be no bonds.
+    fn remove_atom(&self, atom: usize) {
+        assert!(
+            self.get_topology().is_empty(),
+            "Atoms of bonded molecules cannot be removed"
+        );
+        self.get_pos().swap_remove(atom);
+        self.get_vel().swap_remove(atom);
+        self.get_acc().swap_remove(atom);
+        self.get_images().swap_remove(atom);
+        self.get_species().swap_remove(atom);
+        self.get_charges().swap_remove(atom);
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -18,18 +64,124 @@ pub struct State<const D: usize> {
     pos: RefCell<Vec<DVector<D>>>,
     vel: RefCell<Vec<DVector<D>>>,
     acc: RefCell<Vec<DVector<D>>>,
+    #[serde(default, with = "images")]
+    images: RefCell<Vec<[i32; D]>>,
+    #[serde(default)]
+    species: RefCell<Vec<usize>>,
+    #[serde(default)]
+    masses: RefCell<Vec<Real>>,
+    #[serde(default)]
+    charges: RefCell<Vec<Real>>,
+    #[serde(default)]
+    topology: RefCell<Topology>,
 }
 
 impl<const D: usize> MolecularState<D> for State<D> {
-    fn get_pos(&self) -> RefMut<Vec<DVector<D>>> {
+    fn get_pos(&self) -> RefMut<'_, Vec<DVector<D>>> {
         self.pos.borrow_mut()
     }
 
-    fn get_vel(&self) -> RefMut<Vec<DVector<D>>> {
+    fn get_vel(&self) -> RefMut<'_, Vec<DVector<D>>> {
         self.vel.borrow_mut()
     }
 
-    fn get_acc(&self) -> RefMut<Vec<DVector<D>>> {
+    fn get_acc(&self) -> RefMut<'_, Vec<DVector<D>>> {
         self.acc.borrow_mut()
     }
+
+    fn get_images(&self) -> RefMut<'_, Vec<[i32; D]>> {
+        self.images.borrow_mut()
+    }
+
+    fn get_species(&self) -> RefMut<'_, Vec<usize>> {
+        self.species.borrow_mut()
+    }
+
+    fn get_masses(&self) -> RefMut<'_, Vec<Real>> {
+        self.masses.borrow_mut()
+    }
+
+    fn get_charges(&self) -> RefMut<'_, Vec<Real>> {
+        self.charges.borrow_mut()
+    }
+
+    fn get_topology(&self) -> RefMut<'_, Topology> {
+        self.topology.borrow_mut()
+    }
+}
+
+/// Image flags as a list of lists, since serde has no impls for `[i32; D]`.
+mod images {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::cell::RefCell;
+
+    pub fn serialize<S: Serializer, const D: usize>(
+        images: &RefCell<Vec<[i32; D]>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let images = images.borrow();
+        let flags: Vec<&[i32]> = images.iter().map(|image| &image[..]).collect();
+        flags.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, De: Deserializer<'de>, const D: usize>(
+        deserializer: De,
+    ) -> Result<RefCell<Vec<[i32; D]>>, De::Error> {
+        let flags = Vec::<Vec<i32>>::deserialize(deserializer)?;
+        let images = flags
+            .into_iter()
+            .map(|image| {
+                image
+                    .try_into()
+                    .map_err(|_| De::Error::custom(format!("image flags must have {} axes", D)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RefCell::new(images))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_round_trip() {
+        let state = State::<2>::default();
+        state.insert_atom(DVector::from([0.5, -0.5]));
+        state.insert_atom(DVector::from([1., 1.]));
+        state.get_images()[1] = [3, -2];
+        state.remove_atom(0);
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains("[[3,-2]]"), "{}", json);
+        let restored: State<2> = serde_json::from_str(&json).unwrap();
+        assert_eq!(vec![[3, -2]], *restored.get_images());
+    }
+
+    #[test]
+    fn topology_round_trip() {
+        let state = State::<3>::default();
+        for _ in 0..3 {
+            state.insert_atom(DVector::default());
+        }
+        *state.get_topology() = Topology::default().chain(0..3);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: State<3> = serde_json::from_str(&json).unwrap();
+        assert_eq!(*state.get_topology(), *restored.get_topology());
+        let old: State<3> = serde_json::from_str(r#"{"pos":[],"vel":[],"acc":[]}"#).unwrap();
+        assert!(old.get_topology().is_empty());
+    }
+
+    #[test]
+    fn masses_by_species() {
+        let state = State::<3>::default();
+        for _ in 0..3 {
+            state.insert_atom(DVector::default());
+        }
+        state.get_species()[1] = 1;
+        state.get_species()[2] = 2;
+        *state.get_masses() = vec![2., 4.];
+        assert_eq!(vec![2., 4., 1.], state.atom_masses());
+    }
 }
@@ -0,0 +1,111 @@
+#![allow(unused, dead_code)]
+
+use d_vector::Real;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Which atoms are bonded into molecules: bonds between pairs, angles at the
+/// middle atom of triples and dihedrals about the middle bond of quadruples.
+/// Impropers keep out-of-plane quadruples apart from the proper dihedrals,
+/// so that the two may be given different potentials. Constraints are rigid
+/// bonds that `Shake` and `Rattle` keep at their length.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Topology {
+    pub bonds: Vec<[usize; 2]>,
+    pub angles: Vec<[usize; 3]>,
+    pub dihedrals: Vec<[usize; 4]>,
+    pub impropers: Vec<[usize; 4]>,
+    pub constraints: Vec<Constraint>,
+}
+
+/// Distance held fixed between two atoms.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Constraint {
+    pub atoms: [usize; 2],
+    pub length: Real,
+}
+
+impl Topology {
+    pub fn bond(mut self, a: usize, b: usize) -> Self {
+        self.bonds.push([a, b]);
+        self
+    }
+
+    pub fn angle(mut self, a: usize, vertex: usize, b: usize) -> Self {
+        self.angles.push([a, vertex, b]);
+        self
+    }
+
+    pub fn dihedral(mut self, atoms: [usize; 4]) -> Self {
+        self.dihedrals.push(atoms);
+        self
+    }
+
+    pub fn improper(mut self, atoms: [usize; 4]) -> Self {
+        self.impropers.push(atoms);
+        self
+    }
+
+    pub fn constraint(mut self, a: usize, b: usize, length: Real) -> Self {
+        self.constraints.push(Constraint {
+            atoms: [a, b],
+            length,
+        });
+        self
+    }
+
+    /// Linear chain through consecutive atoms: bonds, angles and dihedrals
+    /// between its neighbours, as in bead-spring polymers.
+    pub fn chain(mut self, atoms: Range<usize>) -> Self {
+        let atoms: Vec<usize> = atoms.collect();
+        self.bonds.extend(atoms.windows(2).map(|w| [w[0], w[1]]));
+        self.angles
+            .extend(atoms.windows(3).map(|w| [w[0], w[1], w[2]]));
+        self.dihedrals
+            .extend(atoms.windows(4).map(|w| [w[0], w[1], w[2], w[3]]));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bonds.is_empty()
+            && self.angles.is_empty()
+            && self.dihedrals.is_empty()
+            && self.impropers.is_empty()
+            && self.constraints.is_empty()
+    }
+
+    /// Number of atoms the topology needs, one past the largest index in it.
+    pub fn n_atoms(&self) -> usize {
+        let bonds = self.bonds.iter().flatten();
+        let angles = self.angles.iter().flatten();
+        let dihedrals = self.dihedrals.iter().chain(self.impropers.iter()).flatten();
+        let constraints = self.constraints.iter().flat_map(|c| c.atoms.iter());
+        bonds
+            .chain(constraints)
+            .chain(angles)
+            .chain(dihedrals)
+            .map(|j| j + 1)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chains() {
+        let topology = Topology::default()
+            .chain(0..5)
+            .chain(5..7)
+            .improper([0, 1, 2, 7]);
+        assert_eq!(5, topology.bonds.len());
+        assert_eq!(vec![[5, 6]], topology.bonds[4..]);
+        assert_eq!(3, topology.angles.len());
+        assert_eq!(vec![[0, 1, 2, 3], [1, 2, 3, 4]], topology.dihedrals);
+        assert_eq!(8, topology.n_atoms());
+        assert!(Topology::default().chain(0..1).is_empty());
+    }
+}
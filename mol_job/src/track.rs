@@ -1,6 +1,9 @@
 #![allow(unused, dead_code)]
 
-use crate::state::{MolecularState, State};
+use crate::{
+    state::{MolecularState, State},
+    topology::Topology,
+};
 use d_vector::{DVector, Real};
 use std::{
     cell::{Cell, RefCell, RefMut},
@@ -25,18 +28,38 @@ impl Default for Track {
 }
 
 impl MolecularState<3> for Track {
-    fn get_pos(&self) -> RefMut<Vec<DVector<3>>> {
+    fn get_pos(&self) -> RefMut<'_, Vec<DVector<3>>> {
         self.inner.get_pos()
     }
 
-    fn get_vel(&self) -> RefMut<Vec<DVector<3>>> {
+    fn get_vel(&self) -> RefMut<'_, Vec<DVector<3>>> {
         self.inner.get_vel()
     }
 
-    fn get_acc(&self) -> RefMut<Vec<DVector<3>>> {
+    fn get_acc(&self) -> RefMut<'_, Vec<DVector<3>>> {
         self.inner.get_acc()
     }
 
+    fn get_images(&self) -> RefMut<'_, Vec<[i32; 3]>> {
+        self.inner.get_images()
+    }
+
+    fn get_species(&self) -> RefMut<'_, Vec<usize>> {
+        self.inner.get_species()
+    }
+
+    fn get_masses(&self) -> RefMut<'_, Vec<Real>> {
+        self.inner.get_masses()
+    }
+
+    fn get_charges(&self) -> RefMut<'_, Vec<Real>> {
+        self.inner.get_charges()
+    }
+
+    fn get_topology(&self) -> RefMut<'_, Topology> {
+        self.inner.get_topology()
+    }
+
     fn sync(&self, time_now: Real) {
         let json = serde_json::to_string(&self.inner).unwrap();
         writeln!(self.output.borrow_mut(), "{}. {}", time_now, json);
@@ -44,6 +67,7 @@ impl MolecularState<3> for Track {
 }
 
 impl Track {
+    #[allow(clippy::result_large_err)]
     pub fn restore_from<P: AsRef<Path>>(path: P) -> Result<Self, Self> {
         let input = OpenOptions::new().read(true).open(path)?;
         let mut last_line = last_line_of_file(input).ok_or_else(Self::default)?;
@@ -53,6 +77,17 @@ impl Track {
         }
         let last_state: State<3> =
             serde_json::from_str(end_of_track).map_err(|_| Self::default())?;
+        // Tracks written before image flags, species and charges existed.
+        let n_mol = last_state.get_pos().len();
+        if last_state.get_images().len() != n_mol {
+            *last_state.get_images() = vec![[0; 3]; n_mol];
+        }
+        if last_state.get_species().len() != n_mol {
+            *last_state.get_species() = vec![0; n_mol];
+        }
+        if last_state.get_charges().len() != n_mol {
+            *last_state.get_charges() = vec![0.; n_mol];
+        }
         Ok(Self {
             inner: last_state,
             output: RefCell::new(open_track()?),
@@ -68,12 +103,11 @@ impl From<std::io::Error> for Track {
 
 fn open_track() -> std::io::Result<File> {
     OpenOptions::new()
-        .write(true)
         .append(true)
         .create(true)
         .open("track.txt")
 }
 
 pub(crate) fn last_line_of_file(f: File) -> Option<String> {
-    BufReader::new(f).lines().flatten().last()
+    BufReader::new(f).lines().map_while(Result::ok).last()
 }
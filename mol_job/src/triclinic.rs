@@ -0,0 +1,331 @@
+#![allow(unused, dead_code)]
+
+use crate::boundaries::{fold, BoundaryConditions};
+use d_vector::{DVector, Real};
+
+/// Periodic cell spanned by `D` arbitrary lattice vectors, e.g. the natural
+/// cell of a hexagonal or monoclinic crystal. Positions are kept at fractional
+/// coordinates in `[-1/2, 1/2)` along every lattice vector.
+///
+/// There is no axis-aligned box, so pair searches fall back to all pairs.
+/// The cutoff of the potential must stay below `inscribed_radius`.
+#[derive(Debug)]
+pub struct Triclinic<const D: usize> {
+    vectors: [[Real; D]; D],
+    /// Rows are the reciprocal vectors: fractional coordinate `i` of a position
+    /// is its dot product with row `i`.
+    reciprocal: [[Real; D]; D],
+}
+
+impl<const D: usize> Triclinic<D> {
+    /// Cell with lattice vectors `vectors[0]`, `vectors[1]`, ...
+    pub fn new(vectors: [[Real; D]; D]) -> Self {
+        let mut cell = Self {
+            vectors,
+            reciprocal: [[0.; D]; D],
+        };
+        cell.update_reciprocal();
+        cell
+    }
+
+    pub fn vectors(&self) -> &[[Real; D]; D] {
+        &self.vectors
+    }
+
+    pub fn fractional(&self, position: &DVector<D>) -> [Real; D] {
+        let mut s = [0 as Real; D];
+        for (s, b) in s.iter_mut().zip(self.reciprocal.iter()) {
+            *s = position * &DVector::from(b);
+        }
+        s
+    }
+
+    pub fn cartesian(&self, fractional: &[Real; D]) -> DVector<D> {
+        let mut r = [0 as Real; D];
+        for (s, a) in fractional.iter().zip(self.vectors.iter()) {
+            for (r, a) in r.iter_mut().zip(a) {
+                *r += s * a;
+            }
+        }
+        DVector::from(r)
+    }
+
+    /// Radius of the largest sphere that fits in the cell: half the smallest
+    /// distance between opposite faces.
+    pub fn inscribed_radius(&self) -> Real {
+        self.reciprocal
+            .iter()
+            .map(|b| 0.5 / DVector::from(b).length())
+            .fold(Real::INFINITY, Real::min)
+    }
+
+    /// Applies the deformation gradient `gradient` to the cell and to the atoms,
+    /// as a fully flexible barostat does: every vector `r` becomes `gradient · r`.
+    pub fn deform(&mut self, gradient: &[[Real; D]; D], pos: &mut [DVector<D>]) {
+        for a in self.vectors.iter_mut() {
+            *a = *apply(gradient, &DVector::from(*a)).components();
+        }
+        for position in pos.iter_mut() {
+            *position = apply(gradient, position);
+        }
+        self.update_reciprocal();
+    }
+
+    fn update_reciprocal(&mut self) {
+        // Columns of the matrix are the lattice vectors.
+        let mut matrix = [[0.; D]; D];
+        for (i, a) in self.vectors.iter().enumerate() {
+            for (j, a) in a.iter().enumerate() {
+                matrix[j][i] = *a;
+            }
+        }
+        self.reciprocal =
+            invert(matrix).expect("Lattice vectors of a triclinic cell must be independent");
+    }
+}
+
+impl<const D: usize> BoundaryConditions<D> for Triclinic<D> {
+    fn wrap(&self, pos: &mut DVector<D>) -> [i32; D] {
+        let mut s = self.fractional(pos);
+        let mut image = [0; D];
+        for (s, n) in s.iter_mut().zip(image.iter_mut()) {
+            (*s, *n) = fold(*s, 1.);
+        }
+        *pos = self.cartesian(&s);
+        image
+    }
+
+    /// Rounding fractional coordinates finds the nearest image only in cells
+    /// that are not too skewed, so neighbouring images are checked as well
+    /// unless the result is already shorter than `inscribed_radius`.
+    fn minimum_image(&self, dr: &mut DVector<D>) {
+        let mut s = self.fractional(dr);
+        for s in s.iter_mut() {
+            *s -= s.round();
+        }
+        let mut best = self.cartesian(&s);
+        let r_in = self.inscribed_radius();
+        if best.square_length() <= r_in * r_in {
+            *dr = best;
+            return;
+        }
+        for code in 0..3_usize.pow(D as u32) {
+            let mut shifted = s;
+            let mut code = code;
+            for s in shifted.iter_mut() {
+                *s += (code % 3) as Real - 1.;
+                code /= 3;
+            }
+            let candidate = self.cartesian(&shifted);
+            if candidate.square_length() < best.square_length() {
+                best = candidate;
+            }
+        }
+        *dr = best;
+    }
+
+    fn unwrap(&self, position: &DVector<D>, image: &[i32; D]) -> DVector<D> {
+        let mut s = self.fractional(position);
+        for (s, n) in s.iter_mut().zip(image) {
+            *s += *n as Real;
+        }
+        self.cartesian(&s)
+    }
+
+    fn volume(&self) -> Real {
+        let mut matrix = self.vectors;
+        determinant(&mut matrix).abs()
+    }
+
+    fn at_fractional(&self, fractional: &[Real; D]) -> DVector<D> {
+        self.cartesian(fractional)
+    }
+
+    fn scale(&mut self, factor: Real) {
+        for a in self.vectors.iter_mut() {
+            for a in a.iter_mut() {
+                *a *= factor;
+            }
+        }
+        self.update_reciprocal();
+    }
+}
+
+fn apply<const D: usize>(matrix: &[[Real; D]; D], v: &DVector<D>) -> DVector<D> {
+    let mut result = [0 as Real; D];
+    for (r, row) in result.iter_mut().zip(matrix.iter()) {
+        *r = v * &DVector::from(row);
+    }
+    DVector::from(result)
+}
+
+/// Gauss–Jordan elimination with partial pivoting.
+fn invert<const D: usize>(mut matrix: [[Real; D]; D]) -> Option<[[Real; D]; D]> {
+    let mut inverse = [[0.; D]; D];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    let scale = matrix
+        .iter()
+        .flatten()
+        .fold(0 as Real, |m, x| m.max(x.abs()));
+    for col in 0..D {
+        let pivot =
+            (col..D).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))?;
+        if matrix[pivot][col].abs() <= Real::EPSILON * scale {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let p = matrix[col][col];
+        for k in 0..D {
+            matrix[col][k] /= p;
+            inverse[col][k] /= p;
+        }
+        for row in 0..D {
+            if row != col {
+                let factor = matrix[row][col];
+                for k in 0..D {
+                    matrix[row][k] -= factor * matrix[col][k];
+                    inverse[row][k] -= factor * inverse[col][k];
+                }
+            }
+        }
+    }
+    Some(inverse)
+}
+
+fn determinant<const D: usize>(matrix: &mut [[Real; D]; D]) -> Real {
+    let mut det = 1.;
+    for col in 0..D {
+        let Some(pivot) =
+            (col..D).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))
+        else {
+            return 0.;
+        };
+        if matrix[pivot][col] == 0. {
+            return 0.;
+        }
+        if pivot != col {
+            matrix.swap(col, pivot);
+            det = -det;
+        }
+        det *= matrix[col][col];
+        let pivot_row = matrix[col];
+        for row in matrix[(col + 1)..].iter_mut() {
+            let factor = row[col] / pivot_row[col];
+            for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+        }
+    }
+    det
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundaries::Region;
+    use proptest::prelude::*;
+
+    fn sheared() -> Triclinic<2> {
+        Triclinic::new([[2., 0.], [1.7, 1.]])
+    }
+
+    #[test]
+    fn monoclinic_volume() {
+        let angle = (100 as Real).to_radians();
+        let cell = Triclinic::new([
+            [3., 0., 0.],
+            [0., 4., 0.],
+            [5. * angle.cos(), 0., 5. * angle.sin()],
+        ]);
+        assert!((cell.volume() - 60. * angle.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn dependent_vectors() {
+        Triclinic::new([[1., 2.], [2., 4.]]);
+    }
+
+    proptest! {
+        #[test]
+        fn wrap_keeps_fractional_coordinates_in_cell(x in prop::array::uniform2(-50 as Real..50.)) {
+            let cell = sheared();
+            let mut position = DVector::from(x);
+            let image = cell.wrap(&mut position);
+            for s in cell.fractional(&position) {
+                prop_assert!((-0.5 - 1e-5..0.5 + 1e-5).contains(&s));
+            }
+            let unwrapped = cell.unwrap(&position, &image);
+            prop_assert!((&unwrapped - &DVector::from(x)).length() < 1e-3);
+        }
+
+        #[test]
+        fn minimum_image_beats_every_image(x in prop::array::uniform2(-50 as Real..50.)) {
+            let cell = sheared();
+            let mut dr = DVector::from(x);
+            cell.minimum_image(&mut dr);
+            let s = cell.fractional(&DVector::from(x));
+            for n0 in -60..=60 {
+                for n1 in -60..=60 {
+                    let image = cell.cartesian(&[s[0] + n0 as Real, s[1] + n1 as Real]);
+                    prop_assert!(dr.length() <= image.length() + 1e-4);
+                }
+            }
+        }
+
+        #[test]
+        fn orthogonal_cell_matches_region(
+            dimensions in prop::array::uniform3(0.5 as Real..20.),
+            x in prop::array::uniform3(-100 as Real..100.),
+        ) {
+            let region = Region::new(dimensions);
+            let cell = Triclinic::new([
+                [dimensions[0], 0., 0.],
+                [0., dimensions[1], 0.],
+                [0., 0., dimensions[2]],
+            ]);
+            let (mut a, mut b) = (DVector::from(x), DVector::from(x));
+            region.minimum_image(&mut a);
+            cell.minimum_image(&mut b);
+            prop_assert!((&a - &b).length() < 1e-3);
+            prop_assert!((region.volume() - cell.volume()).abs() < 1e-3 * region.volume());
+        }
+    }
+
+    #[test]
+    fn triangular_crystal_is_balanced() {
+        use crate::{lennard_jones::LennardJones, potential::PotentialEnergy};
+
+        let (a, n) = (1.1, 6);
+        let half_height = (3 as Real).sqrt() / 2.;
+        let cell = Triclinic::new([
+            [n as Real * a, 0.],
+            [n as Real * a / 2., n as Real * a * half_height],
+        ]);
+        assert!(cell.inscribed_radius() > 2.5);
+        let mut pos = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                pos.push(cell.cartesian(&[i as Real / n as Real, j as Real / n as Real]));
+            }
+        }
+        let mut acc = vec![DVector::default(); pos.len()];
+        let potential = LennardJones::default();
+        potential.compute_forces(&pos, &mut acc, &cell);
+        assert!(acc.iter().all(|a| a.length() < 1e-3));
+    }
+
+    #[test]
+    fn deform_follows_atoms() {
+        let mut cell = Triclinic::new([[2., 0.], [0., 2.]]);
+        let mut pos = vec![DVector::from([0.5, 0.5])];
+        let before = cell.fractional(&pos[0]);
+        cell.deform(&[[1., 0.3], [0., 1.1]], &mut pos);
+        let after = cell.fractional(&pos[0]);
+        assert!((before[0] - after[0]).abs() < 1e-6 && (before[1] - after[1]).abs() < 1e-6);
+        assert!((cell.volume() - 4.4).abs() < 1e-5);
+    }
+}
@@ -6,26 +6,58 @@ use crate::{
 use d_vector::{DVector, Real};
 use std::{cell::RefMut, ops::AddAssign};
 
+/// Kick-drift-kick of atoms of the given `masses`, one per atom.
+#[allow(clippy::too_many_arguments)]
 pub fn single_step<const D: usize>(
     delta_t: Real,
     pos: &mut [DVector<D>],
     vel: &mut [DVector<D>],
     acc: &mut [DVector<D>],
+    images: &mut Vec<[i32; D]>,
+    masses: &[Real],
     boundaries: &dyn BoundaryConditions<D>,
     potential_energy: &dyn PotentialEnergy<D>,
 ) {
     leapfrog_begin(delta_t, pos, vel, acc);
-    apply_boundary_conditions(boundaries, pos);
-    potential_energy.compute_forces(pos, acc, boundaries);
+    apply_boundary_conditions(boundaries, pos, vel, images);
+    compute_accelerations(potential_energy, pos, acc, masses, boundaries);
     leapfrog_end(delta_t, vel, acc);
 }
 
+/// Forces of the potential divided by the mass of every atom; potentials
+/// themselves know nothing about masses.
+pub fn compute_accelerations<const D: usize>(
+    potential_energy: &dyn PotentialEnergy<D>,
+    pos: &[DVector<D>],
+    acc: &mut [DVector<D>],
+    masses: &[Real],
+    boundaries: &dyn BoundaryConditions<D>,
+) {
+    assert_eq!(acc.len(), masses.len());
+    potential_energy.compute_forces(pos, acc, boundaries);
+    for (acceleration, mass) in acc.iter_mut().zip(masses) {
+        *acceleration = (1. / mass) * &*acceleration;
+    }
+}
+
+/// Confines every atom to the box, adding the boxes it crossed to its image.
+/// Images that do not match the atoms, as in a state filled directly, start
+/// over from zero.
 pub fn apply_boundary_conditions<const D: usize>(
     boundaries: &dyn BoundaryConditions<D>,
     pos: &mut [DVector<D>],
+    vel: &mut [DVector<D>],
+    images: &mut Vec<[i32; D]>,
 ) {
-    for position in pos.iter_mut() {
-        boundaries.wrap(position)
+    assert_eq!(pos.len(), vel.len());
+    if images.len() != pos.len() {
+        *images = vec![[0; D]; pos.len()];
+    }
+    for ((position, velocity), image) in pos.iter_mut().zip(vel.iter_mut()).zip(images) {
+        let crossed = boundaries.confine(position, velocity);
+        for (n, c) in image.iter_mut().zip(crossed) {
+            *n += c;
+        }
     }
 }
 
@@ -3,7 +3,7 @@ use d_vector::Real;
 use mol_job::lennard_jones::LennardJones;
 
 use crate::{
-    resources::{Acc, Pos, Settings, TimeNow, Vel, Wrapper},
+    resources::{Acc, Images, Pos, Settings, TimeNow, Vel, Wrapper},
     SCALE_X, SCALE_Y, SCALE_Z,
 };
 
@@ -47,17 +47,21 @@ fn single_step(
     mut pos: ResMut<Pos>,
     mut vel: ResMut<Vel>,
     mut acc: ResMut<Acc>,
+    mut images: ResMut<Images>,
     boundaries: Res<Wrapper>,
     mut time: ResMut<TimeNow>,
     settings: Res<Settings>,
 ) {
     let delta_t = settings.delta_t;
     let potential_energy = LennardJones::default();
+    let masses = vec![1.; pos.0.len()];
     mol_job::verlet::single_step(
         delta_t,
         &mut pos.0,
         &mut vel.0,
         &mut acc.0,
+        &mut images.0,
+        &masses,
         &boundaries.0,
         &potential_energy,
     );
@@ -88,8 +92,8 @@ fn check_vel(vel: Res<Vel>, settings: Res<Settings>, boundaries: Res<Wrapper>, t
 
 fn make_translation(components: &[Real; 3]) -> Vec3 {
     Vec3::new(
-        components[0] * SCALE_X,
-        components[1] * SCALE_Y,
-        components[2] * SCALE_Z,
+        components[0] as f32 * SCALE_X,
+        components[1] as f32 * SCALE_Y,
+        components[2] as f32 * SCALE_Z,
     )
 }
@@ -1,6 +1,11 @@
 use bevy::prelude::*;
 use d_vector::{DVector, Real};
-use mol_job::{boundaries::Region, initial_state::cubic_lattice};
+use mol_job::{
+    boundaries::Region,
+    initial_state::cubic_lattice,
+    lennard_jones::LennardJones,
+    minimize::{Fire, Minimizer},
+};
 
 use crate::{DELTA_T, DENSITY, N_MOL, TAU};
 
@@ -20,20 +25,24 @@ pub struct Vel(pub Vec<MolVector>);
 
 pub struct Acc(pub Vec<MolVector>);
 
+pub struct Images(pub Vec<[i32; 3]>);
+
 pub struct Wrapper(pub Region<3>);
 
 pub struct TimeNow(pub Real);
 
 pub fn init(commands: &mut Commands) {
     let settings = load_env();
-    let (boundaries, pos) = cubic_lattice::<3>(settings.n_mol, settings.density);
-    let camera_components = *boundaries.dimensions();
+    let (boundaries, mut pos) = cubic_lattice::<3>(settings.n_mol, settings.density);
+    Fire::default().minimize(&mut pos, &boundaries, &LennardJones::default());
+    let camera_components = boundaries.dimensions().map(|c| c as f32);
     let n_mol_actual = pos.len();
     commands.insert_resource(Pos(pos));
     commands.insert_resource(Vel(vec![MolVector::default(); n_mol_actual]));
     commands.insert_resource(Acc(vec![MolVector::default(); n_mol_actual]));
+    commands.insert_resource(Images(vec![[0; 3]; n_mol_actual]));
     commands.insert_resource(Wrapper(boundaries));
-    commands.insert_resource(TimeNow(0_f32));
+    commands.insert_resource(TimeNow(0 as Real));
     commands.insert_resource(settings);
     commands.spawn_bundle(Camera3dBundle {
         transform: Transform::from_xyz(camera_components[0] + 1., camera_components[1] + 1., camera_components[2] + 1.)